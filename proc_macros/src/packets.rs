@@ -3,7 +3,7 @@ use proc_macro::TokenStream;
 use proc_macro2::{Literal, Span, TokenTree};
 use quote::quote;
 use syn::{
-    braced,
+    braced, bracketed,
     parse::{Parse, ParseStream},
     punctuated::Punctuated,
     Expr, Ident, Result, Token, Type,
@@ -59,8 +59,40 @@ impl Parse for State {
     }
 }
 
+// Either a single id used by every protocol version, or `[versions => id, ...]`
+enum PacketId {
+    Fixed(Box<Expr>),
+    Versioned(Vec<(Expr, Expr)>),
+}
+
+impl Parse for PacketId {
+    fn parse(input: ParseStream) -> Result<Self> {
+        if !input.peek(syn::token::Bracket) {
+            return Ok(PacketId::Fixed(input.parse()?));
+        }
+
+        let content;
+        bracketed!(content in input);
+
+        let mut ids = Vec::new();
+        while !content.is_empty() {
+            // Version ranges are used as match patterns, e.g. `754`, `755..=758` or `759..`
+            let versions = content.parse::<Expr>()?;
+            content.parse::<Token![=>]>()?;
+            let id = content.parse::<Expr>()?;
+            ids.push((versions, id));
+
+            if !content.is_empty() {
+                content.parse::<Token![,]>()?;
+            }
+        }
+
+        Ok(PacketId::Versioned(ids))
+    }
+}
+
 struct Packet {
-    id: Expr,
+    id: PacketId,
     ident: Ident,
     traits: Vec<Ident>,
     fields: Punctuated<Field, Token![,]>,
@@ -249,10 +281,22 @@ fn ident_cat(ident: &Ident, suffix: &Ident) -> Ident {
 }
 
 struct PacketInfo {
-    id: Expr,
     full_ident: Ident,
     path: Vec<Ident>,
     traits: Vec<Ident>,
+    // Variant of PacketState the packet is sent in, None for internal packets
+    state: Option<Ident>,
+}
+
+fn packet_state(state: &Ident) -> Option<Ident> {
+    let variant = match state.to_string().as_str() {
+        "Handshaking" => "Handshake",
+        "Status" => "Status",
+        "Login" => "Login",
+        "Play" => "Play",
+        _ => return None,
+    };
+    Some(Ident::new(variant, state.span()))
 }
 
 pub fn packets(items: TokenStream) -> TokenStream {
@@ -269,10 +313,16 @@ pub fn packets(items: TokenStream) -> TokenStream {
 
             for packet in &state.packets {
                 let mut fields = Vec::new();
+                let mut field_values = Vec::new();
 
                 let mut exclude = Vec::new();
                 for field in &packet.fields {
                     if let Some(either::Left(ident)) = &field.length {
+                        // `remain` takes the rest of the packet, there's no length field
+                        if ident == "remain" {
+                            continue;
+                        }
+
                         // Find field with the same name as the length
                         let length_field = packet
                             .fields
@@ -291,13 +341,16 @@ pub fn packets(items: TokenStream) -> TokenStream {
                     let ty = &field.ty;
 
                     // Exclude length fields from the struct if not public
-                    if exclude.iter().any(|e| *e == ident) {
+                    if exclude.contains(&ident) {
                         continue;
                     }
 
                     fields.push(quote! {
                         pub #ident: #ty,
                     });
+                    field_values.push(quote! {
+                        (stringify!(#ident), format!("{:?}", self.#ident)),
+                    });
                 }
 
                 let packet_ident = packet.ident.clone();
@@ -308,8 +361,38 @@ pub fn packets(items: TokenStream) -> TokenStream {
                     }
                 });
 
+                // Resolves the packet's id for a given protocol version
+                let id_fn = match &packet.id {
+                    PacketId::Fixed(id) => quote! {
+                        pub const fn id(_protocol: u32) -> Option<v32> {
+                            Some(v32::new(#id))
+                        }
+                    },
+                    PacketId::Versioned(ids) => {
+                        let versions = ids.iter().map(|(versions, _)| versions);
+                        let ids = ids.iter().map(|(_, id)| id);
+                        quote! {
+                            pub const fn id(protocol: u32) -> Option<v32> {
+                                match protocol {
+                                    #(#versions => Some(v32::new(#ids)),)*
+                                    _ => None,
+                                }
+                            }
+                        }
+                    }
+                };
+                packets.push(quote! {
+                    impl #packet_ident {
+                        #id_fn
+
+                        // Field names and their Debug output, for inspecting packets
+                        pub fn fields(&self) -> Vec<(&'static str, String)> {
+                            vec![#(#field_values)*]
+                        }
+                    }
+                });
+
                 packet_info.push(PacketInfo {
-                    id: packet.id.clone(),
                     full_ident: ident_cat(
                         &ident_camel(&direction.ident),
                         &ident_cat(&ident_camel(&state.ident), &ident_camel(&packet.ident)),
@@ -320,6 +403,7 @@ pub fn packets(items: TokenStream) -> TokenStream {
                         packet.ident.clone(),
                     ],
                     traits: packet.traits.clone(),
+                    state: packet_state(&state.ident),
                 });
 
                 if packet
@@ -338,13 +422,18 @@ pub fn packets(items: TokenStream) -> TokenStream {
                     let ty = &field.ty;
 
                     // Exclude length fields from the struct if not public
-                    let should_encode = !exclude.iter().any(|e| *e == ident);
+                    let should_encode = !exclude.contains(&ident);
                     if should_encode {
                         decode_param.push(quote! {
                             #ident,
                         });
                     }
 
+                    let field_name = ident.to_string();
+                    decode.push(quote! {
+                        decoder.set_field(#field_name);
+                    });
+
                     if let Some(length) = &field.length {
                         // if Length exists, then field is a Vec
                         // We need to decode Vec<ty> from ty first
@@ -356,7 +445,7 @@ pub fn packets(items: TokenStream) -> TokenStream {
                                 if length_ident == "remain" {
                                     decode.push(quote! {
                                         let mut #ident = Vec::new();
-                                        while $decoder.remaining() > 0 {
+                                        while decoder.remaining() > 0 {
                                             #ident.push(<#ty as serial::Decode>::decode(decoder)?);
                                         }
                                     });
@@ -364,7 +453,8 @@ pub fn packets(items: TokenStream) -> TokenStream {
                                     let length = ident_strcat(length_ident, "_usize");
                                     decode.push(quote! {
                                         let #length = u32::from(#length_ident) as usize;
-                                        let mut #ident = Vec::with_capacity(#length);
+                                        // Never trust the client's length for the allocation size
+                                        let mut #ident = Vec::with_capacity(#length.min(decoder.remaining()));
                                         for _ in 0..#length {
                                             #ident.push(<#ty as serial::Decode>::decode(decoder)?);
                                         }
@@ -394,6 +484,17 @@ pub fn packets(items: TokenStream) -> TokenStream {
                             encode.push(quote! {
                                 serial::Encode::encode(&self.#ident, encoder)?;
                             });
+                        } else {
+                            // Length fields that aren't kept are derived from the Vec they describe
+                            let vec_ident = &packet
+                                .fields
+                                .iter()
+                                .find(|f| matches!(&f.length, Some(either::Left(l)) if l == ident))
+                                .unwrap()
+                                .ident;
+                            encode.push(quote! {
+                                serial::Encode::encode(&<#ty>::from(self.#vec_ident.len() as u32), encoder)?;
+                            });
                         }
                     }
                 }
@@ -415,47 +516,49 @@ pub fn packets(items: TokenStream) -> TokenStream {
                     }
                 });
 
-                if packet
-                    .traits
-                    .contains(&Ident::new("Ignore", Span::call_site()))
-                {
-                    let id = packet.id.clone();
-                    decode_packets.push(quote! {
-                        #id => {
-                            error!("Packet with id {} known but undecodable", #id);
-                            None
+                let full_ident = ident_cat(
+                    &ident_camel(&direction.ident),
+                    &ident_cat(&ident_camel(&state.ident), &ident_camel(&packet.ident)),
+                );
+                let ident = packet.ident.clone();
+                decode_packets.push(quote! {
+                    if #ident::id(protocol) == Some(id) {
+                        let mut decoder = serial::Decoder::new(data);
+                        let packet = <#ident as serial::Decode>::decode(&mut decoder).map_err(|error| {
+                            packet_error(PacketErrorKind::Decode {
+                                field: decoder.field(),
+                                offset: decoder.offset(),
+                                error,
+                            })
+                        })?;
+
+                        if decoder.remaining() > 0 {
+                            return Err(packet_error(PacketErrorKind::TrailingBytes {
+                                offset: decoder.offset(),
+                            }));
                         }
-                    });
-                } else {
-                    let id = packet.id.clone();
-                    let full_ident = ident_cat(
-                        &ident_camel(&direction.ident),
-                        &ident_cat(&ident_camel(&state.ident), &ident_camel(&packet.ident)),
-                    );
-                    let ident = packet.ident.clone();
-                    decode_packets.push(quote! {
-                        #id => {
-                            let (packet, _) = serial::decode_from_slice::<#ident>(&data).unwrap();
-                            Some(Packets::#full_ident(Box::new(packet)))
-                        },
-                    });
-                }
+                        return Ok(Packets::#full_ident(Box::new(packet)));
+                    }
+                });
             }
 
             let state_ident = ident_strcat(&ident_lower(&state.ident), "_packets");
+            let direction_name = direction.ident.to_string();
+            let state_name = state.ident.to_string();
             states.push(quote! {
                 pub mod #state_ident {
                     use super::*;
-                    use log::error;
-
-                    pub fn decode_packet(id: u8, data: Vec<u8>) -> Option<Packets> {
-                        match id {
-                            #(#decode_packets)*
-                            _ => {
-                                error!("Unknown packet id: {}", id);
-                                None
-                            }
-                        }
+
+                    pub fn decode_packet(protocol: u32, id: v32, data: &[u8]) -> Result<Packets, PacketError> {
+                        let packet_error = |kind| PacketError {
+                            direction: #direction_name,
+                            state: #state_name,
+                            id,
+                            kind,
+                        };
+
+                        #(#decode_packets)*
+                        Err(packet_error(PacketErrorKind::UnknownId))
                     }
 
                     #(#packets)*
@@ -495,12 +598,14 @@ pub fn packets(items: TokenStream) -> TokenStream {
     let mut packet_impl_id = Vec::new();
     let mut packet_impl_data = Vec::new();
 
+    let mut packet_impl_state = Vec::new();
+    let mut packet_impl_fields = Vec::new();
+
     let mut packet_debug = Vec::new();
 
     for packet in packet_info {
         let full_ident = &packet.full_ident;
         let path = &packet.path;
-        let id = &packet.id;
 
         packets.push(quote! {
             #full_ident(Box<#(#path)::*>)
@@ -514,7 +619,7 @@ pub fn packets(items: TokenStream) -> TokenStream {
         });
 
         packet_impl_id.push(quote! {
-            #full_ident(..) => #id,
+            #full_ident(..) => #(#path)::*::id(protocol),
         });
         if packet
             .traits
@@ -529,6 +634,15 @@ pub fn packets(items: TokenStream) -> TokenStream {
             });
         }
 
+        packet_impl_fields.push(quote! {
+            #full_ident(packet) => packet.fields(),
+        });
+
+        packet_impl_state.push(match &packet.state {
+            Some(state) => quote! { #full_ident(..) => Some(PacketState::#state), },
+            None => quote! { #full_ident(..) => None, },
+        });
+
         packet_debug.push(quote! {
             #full_ident(packet) => write!(f, stringify!(#full_ident))?,
         });
@@ -539,7 +653,8 @@ pub fn packets(items: TokenStream) -> TokenStream {
             #(#packets,)*
         }
         impl Packets {
-            pub fn get_id(&self) -> u8 {
+            // None if the packet doesn't exist in this protocol version
+            pub fn get_id(&self, protocol: u32) -> Option<v32> {
                 match self {
                     #(Self::#packet_impl_id)*
                 }
@@ -549,6 +664,19 @@ pub fn packets(items: TokenStream) -> TokenStream {
                     #(Self::#packet_impl_data)*
                 }
             }
+            // None for internal packets
+            pub fn get_state(&self) -> Option<PacketState> {
+                match self {
+                    #(Self::#packet_impl_state)*
+                }
+            }
+            // Only used by snap-inspect
+            #[allow(dead_code)]
+            pub fn fields(&self) -> Vec<(&'static str, String)> {
+                match self {
+                    #(Self::#packet_impl_fields)*
+                }
+            }
         }
         impl std::fmt::Debug for Packets {
             fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -0,0 +1,237 @@
+// Prints the packets in a capture written by the server, see `[capture]` in the config.
+// The server's packet definitions are compiled in directly, so packets decode exactly as the server sees them.
+
+use std::fs::File;
+use std::io::BufReader;
+
+// Most of the server's packet code goes unused here
+#[allow(dead_code, unused_imports)]
+#[path = "../packets/mod.rs"]
+mod packets;
+
+#[allow(dead_code)]
+#[path = "../network/capture/format.rs"]
+mod format;
+
+use format::{Direction, Record, STATES};
+use packets::{types::v32, Packets};
+
+const USAGE: &str = "Usage: snap-inspect [options] <capture file>
+
+Options:
+    --json                One JSON object per line instead of text
+    --id <id>             Only packets with this id, e.g. 0x21. Can be repeated
+    --state <state>       Only packets sent in this state: handshake, status, login or play. Can be repeated
+    --direction <dir>     Only serverbound or clientbound packets
+    --player <name>       Only packets sent to or from this player
+    --connection <n>      Only packets sent on this connection";
+
+// Longer field values are cut off in text output
+const MAX_VALUE_LENGTH: usize = 256;
+
+#[derive(Default)]
+struct Options {
+    json: bool,
+    path: String,
+
+    ids: Vec<u32>,
+    states: Vec<u8>,
+    direction: Option<Direction>,
+    player: Option<String>,
+    connection: Option<u32>,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Self::default();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--json" => options.json = true,
+                "--id" => options.ids.push(parse_id(&value()?)?),
+                "--state" => {
+                    let state = value()?;
+                    match STATES.iter().position(|name| *name == state) {
+                        Some(state) => options.states.push(state as u8),
+                        None => return Err(format!("Unknown state '{}'", state)),
+                    }
+                }
+                "--direction" => {
+                    options.direction = Some(match value()?.as_str() {
+                        "serverbound" => Direction::Serverbound,
+                        "clientbound" => Direction::Clientbound,
+                        direction => return Err(format!("Unknown direction '{}'", direction)),
+                    })
+                }
+                "--player" => options.player = Some(value()?),
+                "--connection" => {
+                    let connection = value()?;
+                    options.connection = Some(
+                        connection
+                            .parse()
+                            .map_err(|_| format!("Invalid connection '{}'", connection))?,
+                    );
+                }
+                "-h" | "--help" => return Err(USAGE.to_string()),
+                _ if arg.starts_with("--") => return Err(format!("Unknown option '{}'", arg)),
+                _ if options.path.is_empty() => options.path = arg,
+                _ => return Err(USAGE.to_string()),
+            }
+        }
+
+        if options.path.is_empty() {
+            return Err(USAGE.to_string());
+        }
+        Ok(options)
+    }
+
+    fn matches(&self, record: &Record) -> bool {
+        (self.ids.is_empty() || self.ids.contains(&record.id))
+            && (self.states.is_empty() || self.states.contains(&record.state))
+            && self
+                .direction
+                .is_none_or(|direction| direction == record.direction)
+            && self
+                .player
+                .as_ref()
+                .is_none_or(|player| player.eq_ignore_ascii_case(&record.player))
+            && self
+                .connection
+                .is_none_or(|connection| connection == record.connection)
+    }
+}
+
+// Accepts hex ids as they're usually written, e.g. 0x0F
+fn parse_id(id: &str) -> Result<u32, String> {
+    let parsed = match id.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => id.parse(),
+    };
+    parsed.map_err(|_| format!("Invalid packet id '{}'", id))
+}
+
+fn decode(record: &Record) -> Result<Packets, String> {
+    let id = v32::from(record.id);
+    let protocol = record.protocol;
+    let data = &record.payload;
+
+    let decoded = match (record.direction, record.state) {
+        (Direction::Serverbound, 0) => packets::serverbound::decode_handshaking(protocol, id, data),
+        (Direction::Serverbound, 1) => packets::serverbound::decode_status(protocol, id, data),
+        (Direction::Serverbound, 2) => packets::serverbound::decode_login(protocol, id, data),
+        (Direction::Serverbound, 3) => packets::serverbound::decode_play(protocol, id, data),
+        (Direction::Clientbound, 1) => packets::clientbound::decode_status(protocol, id, data),
+        (Direction::Clientbound, 2) => packets::clientbound::decode_login(protocol, id, data),
+        (Direction::Clientbound, 3) => packets::clientbound::decode_play(protocol, id, data),
+        (direction, state) => return Err(format!("No {} packets in state {}", direction, state)),
+    };
+    decoded.map_err(|e| e.to_string())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn print_text(record: &Record, elapsed: f64, size: usize) {
+    let state = STATES.get(record.state as usize).unwrap_or(&"unknown");
+    let decoded = decode(record);
+    let name = match &decoded {
+        Ok(packet) => format!("{:?}", packet),
+        Err(_) => "?".to_string(),
+    };
+    let player = match record.player.is_empty() {
+        true => String::new(),
+        false => format!(" {}", record.player),
+    };
+
+    println!(
+        "[+{:.6}s] #{}{} {} {} 0x{:02X} {} ({} bytes)",
+        elapsed, record.connection, player, record.direction, state, record.id, name, size
+    );
+    match decoded {
+        Ok(packet) => {
+            for (field, mut value) in packet.fields() {
+                let length = value.chars().count();
+                if length > MAX_VALUE_LENGTH {
+                    value = value.chars().take(MAX_VALUE_LENGTH).collect();
+                    value.push_str(&format!("... ({} more)", length - MAX_VALUE_LENGTH));
+                }
+                println!("    {}: {}", field, value);
+            }
+        }
+        Err(e) => {
+            println!("    error: {}", e);
+            println!("    payload: {}", hex(&record.payload));
+        }
+    }
+}
+
+fn print_json(record: &Record, elapsed: f64, size: usize) {
+    let mut json = serde_json::json!({
+        "timestamp": record.timestamp,
+        "elapsed": elapsed,
+        "connection": record.connection,
+        "player": record.player,
+        "direction": record.direction.to_string(),
+        "state": STATES.get(record.state as usize),
+        "protocol": record.protocol,
+        "id": record.id,
+        "size": size,
+    });
+    match decode(record) {
+        Ok(packet) => {
+            json["packet"] = format!("{:?}", packet).into();
+            json["fields"] = packet
+                .fields()
+                .into_iter()
+                .map(|(field, value)| (field.to_string(), value.into()))
+                .collect::<serde_json::Map<_, _>>()
+                .into();
+        }
+        Err(e) => {
+            json["error"] = e.into();
+            json["payload"] = hex(&record.payload).into();
+        }
+    }
+    println!("{}", json);
+}
+
+fn run(options: Options) -> Result<(), String> {
+    let file = File::open(&options.path).map_err(|e| format!("{}: {}", options.path, e))?;
+    let mut input = BufReader::new(file);
+    format::read_header(&mut input).map_err(|e| format!("{}: {}", options.path, e))?;
+
+    // Times are shown relative to the first packet in the file
+    let mut start = None;
+    loop {
+        let record = match Record::read(&mut input) {
+            Ok(Some(record)) => record,
+            Ok(None) => break,
+            // The server was most likely stopped partway through writing a packet
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                return Err("Capture ends partway through a packet".to_string())
+            }
+            Err(e) => return Err(e.to_string()),
+        };
+        let start = *start.get_or_insert(record.timestamp);
+        if !options.matches(&record) {
+            continue;
+        }
+
+        let elapsed = record.timestamp.saturating_sub(start) as f64 / 1_000_000.0;
+        let size = v32::byte_size(record.id) + record.payload.len();
+        match options.json {
+            true => print_json(&record, elapsed, size),
+            false => print_text(&record, elapsed, size),
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let result = Options::parse(std::env::args().skip(1)).and_then(run);
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
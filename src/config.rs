@@ -47,6 +47,25 @@ impl<'de> Deserialize<'de> for LogLevel {
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FlushPolicy {
+    // Write packets as soon as they're queued
+    Immediate,
+    // Hold Play packets until the server finishes its tick
+    EndOfTick,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ForwardingMode {
+    None,
+    // Legacy forwarding through the Handshake's server address
+    Bungeecord,
+    // Modern forwarding through the `velocity:player_info` login plugin channel
+    Velocity,
+}
+
 // Load config from file and merge with default config
 lazy_static! {
     pub static ref CONFIG: Config = Config::load("config.toml");
@@ -57,6 +76,7 @@ pub struct Config {
     pub general: GeneralConfig,
     pub network: NetworkConfig,
     pub server: ServerConfig,
+    pub capture: CaptureConfig,
 
     // Don't serialize
     #[serde(skip)]
@@ -70,24 +90,97 @@ pub struct GeneralConfig {
 
 #[derive(Serialize, Deserialize)]
 pub struct NetworkConfig {
+    pub bind: Vec<String>,
     pub port: u16,
+    pub unix_socket: String,
     pub max_players: usize,
-
+    pub reserved_slots: usize,
+    pub full_message: String,
+    pub proxy_protocol: bool,
+
+    pub forwarding: ForwardingConfig,
+    pub throttle: ThrottleConfig,
+    pub rate_limit: RateLimitConfig,
+    pub timeouts: TimeoutConfig,
     pub advanced: AdvancedNetworkConfig,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct ForwardingConfig {
+    pub mode: ForwardingMode,
+    pub secret: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ThrottleConfig {
+    pub connections_per_window: usize,
+    pub window_ms: u64,
+    pub max_per_ip: usize,
+    pub max_pending: usize,
+}
+
+// `rate` per second on average, with bursts of up to `burst`. A rate of 0 disables the limit
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct RateLimit {
+    pub rate: u64,
+    pub burst: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TimeoutConfig {
+    pub handshake_ms: u64,
+    pub login_ms: u64,
+    pub frame_ms: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    pub bytes: RateLimit,
+    pub serverbound: StateRateLimits,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StateRateLimits {
+    pub handshake: RateLimit,
+    pub status: RateLimit,
+    pub login: RateLimit,
+    pub play: RateLimit,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct AdvancedNetworkConfig {
     pub buffer_size: usize,
     pub buffered_packets: usize,
 
-    pub compression_threshold: u32,
+    pub compression_threshold: i32,
     pub compression_level: u32,
+
+    pub flush_policy: FlushPolicy,
+
+    pub session_server: String,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct ServerConfig {
     pub motd: String,
+    pub motd_json: String,
+    pub online_mode: bool,
+    pub ops: Vec<String>,
+    pub whitelist: bool,
+
+    pub version_name: String,
+    pub player_sample: usize,
+    pub hide_player_count: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CaptureConfig {
+    pub enabled: bool,
+    pub directory: String,
+
+    pub players: Vec<String>,
+    pub states: Vec<String>,
+    pub packet_ids: Vec<u32>,
 }
 
 impl Config {
@@ -109,6 +202,8 @@ impl Config {
 
         // Merge default config with config from file
         let cfg_file = toml::from_str(&contents).unwrap();
+        let mut default = default;
+        remove_overridden_arrays(&mut default, &cfg_file);
         let mut cfg = serde_toml_merge::merge(default, cfg_file)
             .unwrap()
             .try_into::<Config>()
@@ -125,6 +220,21 @@ impl Config {
     }
 }
 
+// Merging concatenates arrays, but a list in the config file should replace the default list
+fn remove_overridden_arrays(default: &mut toml::Value, file: &toml::Value) {
+    if let (toml::Value::Table(default), toml::Value::Table(file)) = (default, file) {
+        for (key, file_value) in file {
+            match (default.get_mut(key), file_value) {
+                (Some(toml::Value::Array(_)), toml::Value::Array(_)) => {
+                    default.remove(key);
+                }
+                (Some(value), file_value) => remove_overridden_arrays(value, file_value),
+                (None, _) => {}
+            }
+        }
+    }
+}
+
 impl Drop for Config {
     fn drop(&mut self) {
         self.destroy();
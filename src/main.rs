@@ -1,5 +1,3 @@
-#![feature(async_closure)]
-
 mod config;
 mod network;
 mod packets;
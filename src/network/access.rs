@@ -0,0 +1,230 @@
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use chrono::{DateTime, FixedOffset};
+use lazy_static::lazy_static;
+use log::{info, warn};
+use serde::de::DeserializeOwned;
+
+use crate::{config::CONFIG, packets::types::Uuid};
+
+// Same files and format as vanilla, so lists can be copied over from a vanilla server
+const WHITELIST_PATH: &str = "whitelist.json";
+const BANNED_PLAYERS_PATH: &str = "banned-players.json";
+const BANNED_IPS_PATH: &str = "banned-ips.json";
+
+// e.g. "2023-05-01 12:30:00 +0000"
+const DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S %z";
+const DEFAULT_REASON: &str = "Banned by an operator.";
+
+lazy_static! {
+    static ref LISTS: Mutex<AccessLists> = Mutex::new(AccessLists::new());
+}
+
+#[derive(serde::Deserialize)]
+struct WhitelistEntry {
+    uuid: Option<String>,
+    name: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct PlayerBan {
+    uuid: Option<String>,
+    name: Option<String>,
+    reason: Option<String>,
+    // A date, or "forever"
+    expires: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct IpBan {
+    ip: String,
+    reason: Option<String>,
+    expires: Option<String>,
+}
+
+pub enum LoginDenied {
+    Banned(String, Option<DateTime<FixedOffset>>),
+    IpBanned(String, Option<DateTime<FixedOffset>>),
+    NotWhitelisted,
+}
+
+impl std::fmt::Display for LoginDenied {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (message, reason, expires) = match self {
+            LoginDenied::Banned(reason, expires) => {
+                ("You are banned from this server.", reason, expires)
+            }
+            LoginDenied::IpBanned(reason, expires) => (
+                "Your IP address is banned from this server.",
+                reason,
+                expires,
+            ),
+            LoginDenied::NotWhitelisted => {
+                return write!(f, "You are not white-listed on this server!")
+            }
+        };
+
+        write!(f, "{}\nReason: {}", message, reason)?;
+        if let Some(expires) = expires {
+            write!(
+                f,
+                "\nYour ban will be removed on {}",
+                expires.format(DATE_FORMAT)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// A JSON list, reloaded whenever the file's modification time changes
+struct ListFile<T> {
+    path: &'static str,
+    modified: Option<SystemTime>,
+    entries: Vec<T>,
+}
+
+impl<T: DeserializeOwned> ListFile<T> {
+    fn new(path: &'static str) -> Self {
+        let mut list = Self {
+            path,
+            modified: None,
+            entries: Vec::new(),
+        };
+        list.refresh();
+        list
+    }
+
+    fn refresh(&mut self) {
+        let modified = std::fs::metadata(self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if modified == self.modified {
+            return;
+        }
+        self.modified = modified;
+
+        // A deleted file empties the list
+        if modified.is_none() {
+            if !self.entries.is_empty() {
+                info!("{} was removed", self.path);
+                self.entries.clear();
+            }
+            return;
+        }
+
+        let entries = std::fs::read_to_string(self.path)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| serde_json::from_str(&contents).map_err(|e| e.to_string()));
+        match entries {
+            Ok(entries) => {
+                self.entries = entries;
+                info!("Loaded {} entries from {}", self.entries.len(), self.path);
+            }
+            // Keep the old entries, rather than unbanning everyone over a typo
+            Err(e) => warn!("Failed to load {}: {}", self.path, e),
+        }
+    }
+}
+
+struct AccessLists {
+    whitelist: ListFile<WhitelistEntry>,
+    banned_players: ListFile<PlayerBan>,
+    banned_ips: ListFile<IpBan>,
+}
+
+impl AccessLists {
+    fn new() -> Self {
+        Self {
+            whitelist: ListFile::new(WHITELIST_PATH),
+            banned_players: ListFile::new(BANNED_PLAYERS_PATH),
+            banned_ips: ListFile::new(BANNED_IPS_PATH),
+        }
+    }
+
+    fn refresh(&mut self) {
+        self.whitelist.refresh();
+        self.banned_players.refresh();
+        self.banned_ips.refresh();
+    }
+}
+
+/// Loads the lists up-front, so problems with them are logged at startup
+pub fn load() {
+    lazy_static::initialize(&LISTS);
+}
+
+// Entries are matched on their UUID, or on their name if they don't have a valid one
+fn matches(uuid: Option<&str>, name: Option<&str>, player_uuid: Uuid, username: &str) -> bool {
+    match uuid.and_then(Uuid::parse) {
+        Some(uuid) => uuid == player_uuid,
+        None => name.is_some_and(|name| name.eq_ignore_ascii_case(username)),
+    }
+}
+
+// None for bans that never expire. Unreadable dates are treated as permanent
+fn parse_expiry(expires: &Option<String>) -> Option<DateTime<FixedOffset>> {
+    match expires.as_deref() {
+        None | Some("forever") => None,
+        Some(expires) => match DateTime::parse_from_str(expires, DATE_FORMAT) {
+            Ok(expires) => Some(expires),
+            Err(_) => {
+                warn!("Invalid ban expiry '{}', treating it as permanent", expires);
+                None
+            }
+        },
+    }
+}
+
+fn expired(expires: &Option<DateTime<FixedOffset>>) -> bool {
+    expires.is_some_and(|expires| expires < chrono::Utc::now())
+}
+
+pub fn is_op(uuid: Uuid, username: &str) -> bool {
+    CONFIG
+        .server
+        .ops
+        .iter()
+        .any(|op| matches(Some(op), Some(op), uuid, username))
+}
+
+/// Checks the ban lists, then the whitelist if it's enabled. Ops bypass the whitelist, but not bans
+pub fn check(uuid: Uuid, username: &str, ip: IpAddr) -> Result<(), LoginDenied> {
+    let mut lists = LISTS.lock().unwrap();
+    lists.refresh();
+
+    for ban in &lists.banned_players.entries {
+        if matches(ban.uuid.as_deref(), ban.name.as_deref(), uuid, username) {
+            let expires = parse_expiry(&ban.expires);
+            if !expired(&expires) {
+                let reason = ban.reason.as_deref().unwrap_or(DEFAULT_REASON);
+                return Err(LoginDenied::Banned(reason.to_string(), expires));
+            }
+        }
+    }
+
+    // Dual-stack listeners see IPv4 clients as IPv4-mapped IPv6 addresses
+    let ip = ip.to_canonical();
+    for ban in &lists.banned_ips.entries {
+        if ban.ip.parse::<IpAddr>().is_ok_and(|banned| banned == ip) {
+            let expires = parse_expiry(&ban.expires);
+            if !expired(&expires) {
+                let reason = ban.reason.as_deref().unwrap_or(DEFAULT_REASON);
+                return Err(LoginDenied::IpBanned(reason.to_string(), expires));
+            }
+        }
+    }
+
+    if CONFIG.server.whitelist
+        && !is_op(uuid, username)
+        && !lists
+            .whitelist
+            .entries
+            .iter()
+            .any(|entry| matches(entry.uuid.as_deref(), entry.name.as_deref(), uuid, username))
+    {
+        return Err(LoginDenied::NotWhitelisted);
+    }
+    Ok(())
+}
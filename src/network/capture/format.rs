@@ -0,0 +1,207 @@
+use std::io::{self, Read, Write};
+
+/*
+    Capture files are a header followed by records until the end of the file. All integers are big-endian.
+
+    Header:
+    - magic: 7 bytes, "SNAPCAP"
+    - version: u8, currently 1
+
+    Record:
+    - timestamp: u64, microseconds since the Unix epoch
+    - connection: u32, unique to each connection until the server restarts
+    - direction: u8, 0 for serverbound, 1 for clientbound
+    - state: u8, 0 for handshake, 1 for status, 2 for login, 3 for play
+    - protocol: u32, protocol version the packet was sent with. Needed to tell which packet an id refers to
+    - id: u32, the packet id
+    - player length: u8, then that many bytes of the player's UTF-8 username. Empty until the client has logged in
+    - payload length: u32, then that many bytes of the packet's fields. Excludes the id, and is never compressed or encrypted
+
+    Nothing in here depends on the rest of the server, so tools can include this file on its own.
+*/
+
+pub const MAGIC: &[u8; 7] = b"SNAPCAP";
+pub const VERSION: u8 = 1;
+
+// Indexed by the record's state
+pub const STATES: [&str; 4] = ["handshake", "status", "login", "play"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Serverbound,
+    Clientbound,
+}
+
+impl std::fmt::Display for Direction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Direction::Serverbound => write!(f, "serverbound"),
+            Direction::Clientbound => write!(f, "clientbound"),
+        }
+    }
+}
+
+pub struct Record {
+    pub timestamp: u64,
+    pub connection: u32,
+    pub direction: Direction,
+    pub state: u8,
+    pub protocol: u32,
+    pub id: u32,
+    pub player: String,
+    pub payload: Vec<u8>,
+}
+
+pub fn write_header(output: &mut impl Write) -> io::Result<()> {
+    output.write_all(MAGIC)?;
+    output.write_all(&[VERSION])
+}
+
+pub fn read_header(input: &mut impl Read) -> io::Result<()> {
+    let mut header = [0; 8];
+    input.read_exact(&mut header)?;
+    if &header[..7] != MAGIC {
+        return Err(invalid("Not a capture file".to_string()));
+    }
+    if header[7] != VERSION {
+        return Err(invalid(format!(
+            "Unsupported capture version {}",
+            header[7]
+        )));
+    }
+    Ok(())
+}
+
+impl Record {
+    pub fn write(&self, output: &mut impl Write) -> io::Result<()> {
+        // Usernames are at most 16 characters, anything longer is cut off rather than corrupting the file
+        let player = &self.player.as_bytes()[..self.player.len().min(u8::MAX as usize)];
+
+        let mut bytes = Vec::with_capacity(28 + player.len() + self.payload.len());
+        bytes.extend(self.timestamp.to_be_bytes());
+        bytes.extend(self.connection.to_be_bytes());
+        bytes.push(match self.direction {
+            Direction::Serverbound => 0,
+            Direction::Clientbound => 1,
+        });
+        bytes.push(self.state);
+        bytes.extend(self.protocol.to_be_bytes());
+        bytes.extend(self.id.to_be_bytes());
+        bytes.push(player.len() as u8);
+        bytes.extend(player);
+        bytes.extend((self.payload.len() as u32).to_be_bytes());
+        bytes.extend(&self.payload);
+
+        output.write_all(&bytes)
+    }
+
+    /// Reads the next record, None at the end of the file
+    pub fn read(input: &mut impl Read) -> io::Result<Option<Self>> {
+        let mut timestamp = [0; 8];
+        match input.read_exact(&mut timestamp) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let connection = read_u32(input)?;
+        let direction = match read_u8(input)? {
+            0 => Direction::Serverbound,
+            1 => Direction::Clientbound,
+            direction => return Err(invalid(format!("Invalid direction {}", direction))),
+        };
+        let state = read_u8(input)?;
+        let protocol = read_u32(input)?;
+        let id = read_u32(input)?;
+
+        let mut player = vec![0; read_u8(input)? as usize];
+        input.read_exact(&mut player)?;
+        let player =
+            String::from_utf8(player).map_err(|_| invalid("Invalid player name".to_string()))?;
+
+        // Read rather than allocated up-front, so a corrupt length can't ask for more memory than the file holds
+        let length = read_u32(input)? as u64;
+        let mut payload = Vec::new();
+        input.by_ref().take(length).read_to_end(&mut payload)?;
+        if payload.len() as u64 != length {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        Ok(Some(Self {
+            timestamp: u64::from_be_bytes(timestamp),
+            connection,
+            direction,
+            state,
+            protocol,
+            id,
+            player,
+            payload,
+        }))
+    }
+}
+
+fn read_u8(input: &mut impl Read) -> io::Result<u8> {
+    let mut bytes = [0; 1];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> Record {
+        Record {
+            timestamp: 1_700_000_000_000_000,
+            connection: 7,
+            direction: Direction::Clientbound,
+            state: 3,
+            protocol: 758,
+            id: 0x18,
+            player: "Notch".to_string(),
+            payload: b"\x0fminecraft:brand\x06SnapRS".to_vec(),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut file = Vec::new();
+        write_header(&mut file).unwrap();
+        record().write(&mut file).unwrap();
+        record().write(&mut file).unwrap();
+
+        let mut input = file.as_slice();
+        read_header(&mut input).unwrap();
+        for _ in 0..2 {
+            let read = Record::read(&mut input).unwrap().unwrap();
+            let expected = record();
+            assert_eq!(read.timestamp, expected.timestamp);
+            assert_eq!(read.direction, expected.direction);
+            assert_eq!(read.player, expected.player);
+            assert_eq!(read.payload, expected.payload);
+        }
+        assert!(Record::read(&mut input).unwrap().is_none());
+    }
+
+    #[test]
+    fn corrupt_payload_length() {
+        let mut bytes = Vec::new();
+        record().write(&mut bytes).unwrap();
+        // The payload length comes right after the player name
+        let offset = 8 + 4 + 1 + 1 + 4 + 4 + 1 + "Notch".len();
+        bytes[offset..offset + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+
+        let error = Record::read(&mut bytes.as_slice()).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,179 @@
+// Reading captures is left to snap-inspect
+#[allow(dead_code)]
+pub(crate) mod format;
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::{
+    atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+    mpsc, OnceLock,
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use lazy_static::lazy_static;
+use log::{error, info, warn};
+
+use crate::{config::CONFIG, packets::types::ConnectionState};
+
+pub use format::Direction;
+use format::{Record, STATES};
+
+// Records waiting to be written. Anything captured while the queue is full is dropped
+const QUEUE_LENGTH: usize = 4096;
+
+lazy_static! {
+    // Every connection sends its packets to one thread, which owns the file so disk I/O never holds up a connection
+    static ref RECORDS: mpsc::SyncSender<Record> = spawn_writer();
+}
+
+static NEXT_CONNECTION: AtomicU32 = AtomicU32::new(0);
+// Records dropped since the writer last caught up
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// A connection's capture state, shared between its reader and writer and the server.
+/// Capturing starts straight away when enabled in the config without a player filter,
+/// otherwise once a listed player logs in or the server turns it on.
+pub struct Capture {
+    connection: u32,
+    enabled: AtomicBool,
+    player: OnceLock<String>,
+}
+
+impl Capture {
+    pub fn new() -> Self {
+        let config = &CONFIG.capture;
+        Self {
+            connection: NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed),
+            enabled: AtomicBool::new(config.enabled && config.players.is_empty()),
+            player: OnceLock::new(),
+        }
+    }
+
+    /// Tags everything captured from here on with the player's name
+    pub fn logged_in(&self, username: &str) {
+        let _ = self.player.set(username.to_string());
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        if self.enabled.swap(enabled, Ordering::Relaxed) != enabled {
+            let target = match self.player.get() {
+                Some(player) => player.clone(),
+                None => format!("connection {}", self.connection),
+            };
+            match enabled {
+                true => info!("Started capturing packets from {}", target),
+                false => info!("Stopped capturing packets from {}", target),
+            }
+        }
+    }
+
+    /// Writes a packet to the capture file if capturing is on and the packet passes the config's filters
+    pub fn record(
+        &self,
+        direction: Direction,
+        state: &ConnectionState,
+        protocol: u32,
+        id: u32,
+        payload: &[u8],
+    ) {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let config = &CONFIG.capture;
+        let state = u8::from(state);
+        if !config.states.is_empty()
+            && !config
+                .states
+                .iter()
+                .any(|name| name == STATES[state as usize])
+        {
+            return;
+        }
+        if !config.packet_ids.is_empty() && !config.packet_ids.contains(&id) {
+            return;
+        }
+
+        let record = Record {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_micros() as u64),
+            connection: self.connection,
+            direction,
+            state,
+            protocol,
+            id,
+            player: self.player.get().cloned().unwrap_or_default(),
+            payload: payload.to_vec(),
+        };
+
+        match RECORDS.try_send(record) {
+            Ok(()) => {}
+            // The disk can't keep up, losing packets is better than running out of memory
+            Err(mpsc::TrySendError::Full(_)) => {
+                if DROPPED.fetch_add(1, Ordering::Relaxed) == 0 {
+                    warn!("Capture file is falling behind, dropping packets");
+                }
+            }
+            // The writer has given up on the file
+            Err(mpsc::TrySendError::Disconnected(_)) => {
+                self.enabled.store(false, Ordering::Relaxed)
+            }
+        }
+    }
+}
+
+fn spawn_writer() -> mpsc::SyncSender<Record> {
+    let (sender, receiver) = mpsc::sync_channel::<Record>(QUEUE_LENGTH);
+    std::thread::spawn(move || {
+        // Opened when the first packet is captured
+        let mut file = None;
+        while let Ok(record) = receiver.recv() {
+            if file.is_none() {
+                match open() {
+                    Ok(opened) => file = Some(opened),
+                    Err(e) => {
+                        error!("Failed to create capture file: {}", e);
+                        return;
+                    }
+                }
+            }
+            let file = file.as_mut().unwrap();
+
+            // Flushed once there's nothing left to write, so the file is usable while the server is still running
+            let mut result = record.write(file);
+            while result.is_ok() {
+                match receiver.try_recv() {
+                    Ok(record) => result = record.write(file),
+                    Err(_) => break,
+                }
+            }
+            if let Err(e) = result.and_then(|_| file.flush()) {
+                error!("Failed to write to capture file: {}", e);
+            }
+
+            let dropped = DROPPED.swap(0, Ordering::Relaxed);
+            if dropped > 0 {
+                warn!(
+                    "Dropped {} packets while the capture file was behind",
+                    dropped
+                );
+            }
+        }
+    });
+    sender
+}
+
+fn open() -> std::io::Result<BufWriter<File>> {
+    std::fs::create_dir_all(&CONFIG.capture.directory)?;
+    let path = format!(
+        "{}/{}.snapcap",
+        CONFIG.capture.directory,
+        chrono::Local::now().format("%Y-%m-%d %H_%M_%S")
+    );
+    info!("Capturing packets to {}", path);
+
+    let mut file = BufWriter::new(File::create(path)?);
+    format::write_header(&mut file)?;
+    Ok(file)
+}
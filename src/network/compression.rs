@@ -0,0 +1,79 @@
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+
+use crate::{config::CONFIG, packets::serial::DecodeError};
+
+// Largest uncompressed packet a client may claim to send, same limit as vanilla
+pub const MAX_DECOMPRESSED_LENGTH: usize = 8388608;
+
+/// Packets at least this large get compressed. `None` if compression is disabled.
+pub fn threshold() -> Option<usize> {
+    usize::try_from(CONFIG.network.advanced.compression_threshold).ok()
+}
+
+/// Per-connection deflate state, reset between packets instead of being reallocated
+pub struct Deflater {
+    compress: Compress,
+    output: Vec<u8>,
+}
+
+impl Deflater {
+    pub fn new() -> Self {
+        Self {
+            compress: Compress::new(
+                Compression::new(CONFIG.network.advanced.compression_level),
+                true,
+            ),
+            output: Vec::new(),
+        }
+    }
+
+    /// Compresses `input`, or returns `None` if the result wouldn't be any smaller
+    pub fn compress(&mut self, input: &[u8]) -> Option<&[u8]> {
+        self.compress.reset();
+        self.output.clear();
+        self.output.resize(input.len(), 0);
+
+        match self
+            .compress
+            .compress(input, &mut self.output, FlushCompress::Finish)
+        {
+            Ok(Status::StreamEnd) => {
+                self.output.truncate(self.compress.total_out() as usize);
+                Some(&self.output)
+            }
+            // Ran out of room, so compressing isn't worth it
+            _ => None,
+        }
+    }
+}
+
+/// Per-connection inflate state, reset between packets instead of being reallocated
+pub struct Inflater {
+    decompress: Decompress,
+}
+
+impl Inflater {
+    pub fn new() -> Self {
+        Self {
+            decompress: Decompress::new(true),
+        }
+    }
+
+    /// Inflates `input`, which must decompress to exactly `length` bytes
+    pub fn decompress(&mut self, input: &[u8], length: usize) -> Result<Vec<u8>, DecodeError> {
+        // Never allocate or inflate more than the client could legitimately send
+        if length > MAX_DECOMPRESSED_LENGTH {
+            return Err(DecodeError::InvalidData);
+        }
+
+        self.decompress.reset(true);
+        let mut output = vec![0; length];
+        match self
+            .decompress
+            .decompress(input, &mut output, FlushDecompress::Finish)
+        {
+            Ok(Status::StreamEnd) if self.decompress.total_out() as usize == length => Ok(output),
+            _ => Err(DecodeError::InvalidData),
+        }
+    }
+}
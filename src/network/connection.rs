@@ -1,29 +1,85 @@
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Instant;
 
-use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
-use std::io::prelude::*;
-
-use log::{debug, error, trace, warn};
-use tokio::net::TcpStream;
+use futures::{future::BoxFuture, stream::FuturesUnordered, StreamExt};
+use log::{debug, error, info, trace, warn};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
 use tokio::sync::RwLock;
 
 use crate::{
-    config::CONFIG,
-    packets::{self, serial, Packets},
+    config::{FlushPolicy, ForwardingMode, CONFIG},
+    packets::{self, version::ProtocolVersion, PacketError, PacketErrorKind, Packets},
 };
 
+use super::access;
+use super::capture::{Capture, Direction};
+use super::compression::{self, Inflater};
+use super::encryption::{self, Decryptor, SERVER_KEY};
+use super::forwarding::{self, ForwardedPlayer};
+use super::framing::{unpack_frame, Frame, FrameDecoder};
+use super::keep_alive::KeepAlive;
+use super::login_plugin::{self, LoginPlugins, PluginError};
+use super::metrics::ConnectionMetrics;
+use super::rate_limit::RateLimiter;
+use super::status::{OnlinePlayers, StatusResponse};
+use super::timeout::{self, Deadlines};
+use super::writer::PacketWriter;
+
 use crate::packets::types::*;
 
 use tokio::sync::{
     broadcast,
     mpsc::{Receiver, Sender},
+    oneshot,
 };
 
-const PROTOCOL_VERSION: u32 = 754;
-
 pub struct ServerConnection {
     pub incoming: Receiver<Packets>,
     pub outgoing: Sender<Packets>,
+    pub capture: Arc<Capture>,
+    pub metrics: Arc<ConnectionMetrics>,
+}
+
+// Login progress. Only ever touched by the reader task.
+struct LoginState {
+    // The client's real address, taken from the PROXY header if enabled
+    address: SocketAddr,
+    username: String,
+    verify_token: [u8; 4],
+    decryptor: Option<Decryptor>,
+
+    // Sent by BungeeCord in the Handshake
+    forwarded: Option<ForwardedPlayer>,
+
+    plugins: LoginPlugins,
+    // Login steps waiting on the client, e.g. for a plugin response. Polled by the reader alongside the socket,
+    // anything they return is handled the same as the result of `process_packet`
+    tasks: FuturesUnordered<BoxFuture<'static, Handled>>,
+}
+
+// What the reader does once a packet has been handled
+enum Handled {
+    // Keep reading
+    Done,
+    // Pass the packet on to the server
+    Forward(Packets),
+    // The client has been disconnected, anything else it sent is ignored
+    Close,
+}
+
+impl LoginState {
+    fn new(address: SocketAddr, outgoing: Sender<Packets>) -> Self {
+        Self {
+            address,
+            username: String::new(),
+            verify_token: [0; 4],
+            decryptor: None,
+            forwarded: None,
+            plugins: LoginPlugins::new(outgoing),
+            tasks: FuturesUnordered::new(),
+        }
+    }
 }
 
 pub(crate) struct Connection {
@@ -35,12 +91,23 @@ pub(crate) struct Connection {
 }
 
 impl Connection {
-    pub(crate) async fn new(
-        socket: TcpStream,
-        players: usize,
-    ) -> (Self, ServerConnection, broadcast::Receiver<String>) {
+    pub(crate) async fn new<S>(
+        socket: S,
+        address: SocketAddr,
+        online: OnlinePlayers,
+    ) -> (
+        Self,
+        ServerConnection,
+        broadcast::Receiver<String>,
+        oneshot::Receiver<()>,
+    )
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
         let (inbound, incoming) = tokio::sync::mpsc::channel(32);
         let (outgoing, mut outbound) = tokio::sync::mpsc::channel::<Packets>(32);
+        // Fired once the client has logged in and should be handed over to the server
+        let (logged_in_sender, logged_in) = oneshot::channel();
 
         // How did I get this number? Spamming 'Refresh' in the server list until I didn't get a LAGGED error
         let (ctx, crx) = broadcast::channel(5);
@@ -50,11 +117,15 @@ impl Connection {
         let crx1 = crx.resubscribe();
         let crx2 = crx.resubscribe();
 
-        let (reader, writer) = socket.into_split();
+        let (mut reader, writer) = tokio::io::split(socket);
 
         // Connection States
         let compressed = Arc::new(RwLock::new(false));
         let mut state = Arc::new(RwLock::new(ConnectionState::Handshake));
+        // Picks the packet registry. Set once the Handshake is received
+        let protocol = Arc::new(RwLock::new(ProtocolVersion::latest().protocol));
+        let capture = Arc::new(Capture::new());
+        let metrics = Arc::new(ConnectionMetrics::default());
 
         // TODO: Figure out what to do with recv/send errors
 
@@ -62,142 +133,46 @@ impl Connection {
         let ctxc = ctx.clone();
         let cc = compressed.clone();
         let sc = state.clone();
+        let pc = protocol.clone();
+        let capc = capture.clone();
+        let mc = metrics.clone();
         let writer = tokio::spawn(async move {
             let mut crx = crx1;
-            let ctx = ctxc;
-            let compressed = cc;
             let state = sc;
 
-            let send_packet =
-                async move |packet: Packets,
-                            writer: &tokio::net::tcp::OwnedWriteHalf,
-                            ctx: &broadcast::Sender<String>,
-                            compressed: &Arc<RwLock<bool>>| {
-                    trace!("Sending packet: {}", packet.get_id());
-                    let mut bytes = Vec::new();
-
-                    let id = packet.get_id();
-
-                    let mut should_enable_compression = false; // TODO: Something better
-                    match packet {
-                        Packets::InternalNetworkDisconnect(_) => {
-                            ctx.send("".to_string()).unwrap();
-                        }
-                        Packets::ClientboundLoginDisconnect(packet) => {
-                            bytes.extend(serial::encode_to_vec(packet.as_ref()).unwrap());
-
-                            debug!(
-                                "Disconnecting client: {}",
-                                String::from(packet.reason.value)
-                            );
-                            ctx.send("".to_string()).unwrap();
-                        }
-                        Packets::ClientboundLoginSetCompression(_) => {
-                            should_enable_compression = true;
-                            bytes.extend(packet.get_data());
-                        }
-                        _ => {
-                            bytes.extend(packet.get_data());
-                        }
-                    }
-
-                    // Normal packet
-                    let len = (bytes.len() + 1) as u32;
-                    let mut data = Vec::with_capacity(len as usize + v32::byte_size(len));
-                    data.extend(serial::encode_to_vec(&v32::from(len)).unwrap());
-                    data.push(id);
-                    data.extend(bytes);
-
-                    if *compressed.read().await {
-                        let mut compressed_len = 0;
-                        if len > CONFIG.network.advanced.compression_threshold {
-                            let mut zlib = ZlibEncoder::new(
-                                Vec::new(),
-                                Compression::new(CONFIG.network.advanced.compression_level),
-                            );
-                            zlib.write_all(&data[v32::byte_size(len)..]).unwrap();
-                            let compressed_data = zlib.finish().unwrap();
-
-                            // If our compressed data is smaller, use it
-                            if compressed_data.len() < len as usize {
-                                compressed_len = compressed_data.len() as u32;
-                                data = compressed_data;
-                            }
-                        }
-
-                        // Compressed packet
-                        let skip = v32::byte_size(len);
-
-                        let len = data.len() + v32::byte_size(compressed_len);
-                        let mut bytes = Vec::with_capacity(v32::byte_size(len as u32) + len);
-                        bytes.extend(serial::encode_to_vec(&v32::from(len as u32)).unwrap());
-                        bytes.extend(serial::encode_to_vec(&v32::from(compressed_len)).unwrap());
-                        bytes.extend(data[skip..].iter());
-
-                        data = bytes;
-                    }
-
-                    // TODO: Encryption
-
-                    if data.len() > 2097151 {
-                        error!("Packet too large! {}", data.len());
-                        ctx.send(format!("Server tried sending Packet size {}", data.len()))
-                            .unwrap();
-                        return;
-                    }
-
-                    trace!("Sending {} bytes to client", data.len());
-
-                    // Write to file(debug)
-                    if false {
-                        let mut file = std::fs::OpenOptions::new()
-                            .write(true)
-                            .create(true)
-                            .open("packets.bin")
-                            .unwrap();
-                        file.write_all(&data).unwrap();
-                    }
-
-                    // TODO: Look into performance advantages of batching
-                    let mut written = 0;
-                    while written < data.len() {
-                        writer.writable().await.unwrap();
-                        match writer.try_write(&data[written..]) {
-                            Ok(n) => {
-                                trace!("Wrote {} bytes", n);
-                                written += n;
-                            }
-                            Err(e) => {
-                                if e.kind() != std::io::ErrorKind::WouldBlock {
-                                    error!("Error reading from connection: {}", e);
-                                }
-                                break;
-                            }
-                        }
-                    }
-
-                    /*
-                        As SetCompression is never recieved and packets are only compressed AFTER,
-                        we *shouldn't* have to worry about syncing this with the reader.
-                    */
-                    if should_enable_compression {
-                        *compressed.write().await = true;
-                    }
-                };
+            let mut writer = PacketWriter::new(writer, ctxc, cc, pc, capc, mc);
 
             loop {
                 tokio::select! {
                     Ok(reason) = crx.recv() => {
                         if *(state.read().await) == ConnectionState::Play {
-                            send_packet(Packets::from(packets::clientbound::play_packets::Disconnect {
-                                reason: Chat::from(reason),
-                            }), &writer, &ctx, &compressed).await;
+                            writer.queue(Packets::from(packets::clientbound::play_packets::Disconnect {
+                                reason: Chat::from(serde_json::json!({ "text": reason }).to_string()),
+                            })).await;
                         }
+                        writer.flush().await;
                         break;
                     }
                     packet = outbound.recv() => {
-                        if let Some(packet) = packet {
-                            send_packet(packet, &writer, &ctx, &compressed).await;
+                        let packet = match packet {
+                            Some(packet) => packet,
+                            None => break,
+                        };
+                        let mut flush = matches!(packet, Packets::InternalNetworkFlush(_));
+                        writer.queue(packet).await;
+
+                        // Encode everything that's already waiting, so it goes out in one write
+                        while let Ok(packet) = outbound.try_recv() {
+                            flush |= matches!(packet, Packets::InternalNetworkFlush(_));
+                            writer.queue(packet).await;
+                        }
+
+                        // The server only sends Flush once it owns the connection
+                        if flush
+                            || CONFIG.network.advanced.flush_policy == FlushPolicy::Immediate
+                            || *(state.read().await) != ConnectionState::Play
+                        {
+                            writer.flush().await;
                         }
                     }
                 }
@@ -210,151 +185,290 @@ impl Connection {
         // Read from Client
         let ctxc = ctx.clone();
         let outgoing_clone = outgoing.clone();
+        let capc = capture.clone();
+        let mc = metrics.clone();
         let reader = tokio::spawn(async move {
             let mut crx = crx2;
             let ctx = ctxc;
+            let capture = capc;
+            let metrics = mc;
 
             // Take ownership of 'compressed' as it is not used after this is spawned.
 
             // Buffer for reading data from the client
             let mut buffer = vec![0; CONFIG.network.advanced.buffer_size];
 
+            let mut login = LoginState::new(address, outgoing_clone.clone());
+            let mut limiter = RateLimiter::new();
+            let mut frames = FrameDecoder::with_capacity(CONFIG.network.advanced.buffer_size);
+            // Created once the client is told to start compressing
+            let mut inflater = None;
+            let mut keep_alive = KeepAlive::new();
+            // Taken once the client is in Play
+            let mut logged_in_sender = Some(logged_in_sender);
+            let mut deadlines = Deadlines::new();
+
             'outer: loop {
-                tokio::select! {
+                let deadline = deadlines.next(&*state.read().await);
+                let read = tokio::select! {
                     _ = crx.recv() => {
                         break 'outer;
                     }
-                    _ = reader.readable() => {
+                    read = reader.read(&mut buffer) => read,
+                    Some(handled) = login.tasks.next() => {
+                        match handled {
+                            Handled::Done => {}
+                            Handled::Forward(packet) => {
+                                forward(packet, &state, &inbound, &capture, &mut logged_in_sender).await;
+                            }
+                            Handled::Close => break 'outer,
+                        }
+                        continue;
+                    }
+                    e = timeout::expired(deadline) => {
+                        debug!("Closing connection from {}: {}", address, e);
+                        disconnect(&state, &outgoing_clone, &ctx, "Timed out".to_string()).await;
+                        break 'outer;
+                    }
+                    _ = keep_alive.tick(), if logged_in_sender.is_none() => {
+                        match keep_alive.send() {
+                            Some(id) => outgoing_clone
+                                .send(Packets::from(packets::clientbound::play_packets::KeepAlive { id }))
+                                .await
+                                .unwrap(),
+                            None => {
+                                debug!("Client at {} didn't answer keep-alive", address);
+                                disconnect(&state, &outgoing_clone, &ctx, "Timed out".to_string())
+                                    .await;
+                                break 'outer;
+                            }
+                        }
+                        continue;
                     }
-                }
+                };
 
-                // TODO: Encryption
-                let read = match reader.try_read(&mut buffer) {
+                let read = match read {
                     Ok(0) => {
                         //trace!("Connection closed");
                         ctx.send("".to_string()).unwrap();
-                        0
+                        break 'outer;
                     }
                     Ok(n) => {
                         trace!("Read {} bytes", n);
                         n
                     }
                     Err(e) => {
-                        if e.kind() != std::io::ErrorKind::WouldBlock {
-                            error!("Error reading from connection: {}", e);
-                        }
-                        0
+                        // Usually just the client going away
+                        debug!("Error reading from {}: {}", address, e);
+                        ctx.send("".to_string()).unwrap();
+                        break 'outer;
                     }
                 };
 
-                let mut index = 0;
-                while index < read {
-                    let (length, lsize) =
-                        serial::decode_from_slice::<v32>(&buffer[index..]).unwrap();
-                    let length = u32::from(length) as usize;
-                    index += lsize;
-
-                    let mut packet_bytes = Vec::with_capacity(length);
-
-                    // Ensure we index within buffer bounds
-                    // SAFETY: chunks(length) will never panic as length is always guarenteed to be at least 2
-                    let b = buffer[index..].chunks(length).next().unwrap();
-                    packet_bytes.extend_from_slice(b);
-                    index += b.len();
-
-                    // Ensure we have read the entire packet
-                    // TODO: Check if we can use packet_bytes.capacity() instead
-                    let mut remain = length - packet_bytes.len();
-
-                    // Same reason as above
-                    // SAFETY: length <= packet_bytes.capacity()
-                    packet_bytes.resize(length, 0);
-
-                    while remain > 0 {
-                        trace!("Packet incomplete. {} bytes remaining", remain);
-
-                        reader.readable().await.unwrap();
-                        match reader.try_read(&mut packet_bytes[(length - remain)..]) {
-                            Ok(0) => {
-                                //trace!("Connection closed");
-                                warn!("Connection close while reading packet");
-                                ctx.send("".to_string()).unwrap();
-                                break 'outer;
-                            }
-                            Ok(n) => {
-                                trace!("Read {} bytes", n);
-                                remain -= n;
-                            }
-                            Err(e) => {
-                                if e.kind() != std::io::ErrorKind::WouldBlock {
-                                    error!("Error reading from connection: {}", e);
-                                }
-                            }
-                        }
-                    }
+                if let Err(e) = limiter.bytes(read) {
+                    warn!("Kicking {}: {}", address, e);
+                    disconnect(&state, &outgoing_clone, &ctx, e.to_string()).await;
+                    break 'outer;
+                }
+
+                if let Some(decryptor) = &mut login.decryptor {
+                    decryptor.decrypt(&mut buffer[..read]);
+                }
+
+                // Pre-1.7 clients open with 0xFE instead of a length-prefixed Handshake
+                if read > 0
+                    && buffer[0] == 0xFE
+                    && frames.pending_mut().is_empty()
+                    && *state.read().await == ConnectionState::Handshake
+                {
+                    debug!("Received legacy server list ping");
+                    outgoing_clone
+                        .send(Packets::from(
+                            packets::internal::network_packets::LegacyPingResponse {
+                                data: StatusResponse::new(
+                                    &online.players().await,
+                                    ProtocolVersion::latest(),
+                                )
+                                .to_legacy(),
+                            },
+                        ))
+                        .await
+                        .unwrap();
+                    break 'outer;
+                }
 
-                    let id;
-                    let data;
-
-                    if *compressed.read().await {
-                        let (compressed_len, clsize) =
-                            serial::decode_from_slice::<v32>(&packet_bytes).unwrap();
-                        let compressed_len = u32::from(compressed_len) as usize;
-
-                        if compressed_len > 0 {
-                            let mut bytes = ZlibDecoder::new(&packet_bytes[clsize..]).bytes();
-                            id = bytes.next().unwrap().unwrap();
-
-                            let mut d = bytes.map(|x| x.unwrap()).collect::<Vec<u8>>();
-                            d.shrink_to(compressed_len);
-                            data = d;
-                        } else {
-                            // Start with index of 1 as the expected clsize(0) is guaranteed to be 1 byte.
-                            id = packet_bytes[1];
-                            data = packet_bytes[2..].to_vec();
+                frames.extend(&buffer[..read]);
+
+                let mut completed = false;
+                loop {
+                    let packet_bytes = match frames.next_frame() {
+                        Ok(Frame::Complete(packet_bytes)) => {
+                            completed = true;
+                            packet_bytes
                         }
-                    } else {
-                        id = packet_bytes[0];
-                        data = packet_bytes[1..].to_vec();
+                        Ok(Frame::Incomplete) => {
+                            deadlines.frames_read(completed, !frames.pending_mut().is_empty());
+                            break;
+                        }
+                        Err(e) => {
+                            warn!("Malformed frame from {}: {}", address, e);
+                            disconnect(&state, &outgoing_clone, &ctx, e.to_string()).await;
+                            break 'outer;
+                        }
+                    };
+
+                    let started = Instant::now();
+                    let wire_bytes = packet_bytes.len() + v32::byte_size(packet_bytes.len() as u32);
+
+                    let limited = limiter.packet(&*state.read().await);
+                    if let Err(e) = limited {
+                        warn!("Kicking {}: {}", address, e);
+                        disconnect(&state, &outgoing_clone, &ctx, e.to_string()).await;
+                        break 'outer;
                     }
 
-                    //index += packet_bytes.len();
+                    let inflater = if *compressed.read().await {
+                        Some(inflater.get_or_insert_with(Inflater::new))
+                    } else {
+                        None
+                    };
+                    let (id, data) = match unpack_frame(packet_bytes, inflater) {
+                        Ok(unpacked) => unpacked,
+                        Err(e) => {
+                            warn!("Malformed packet from {}: {:?}", address, e);
+                            disconnect(
+                                &state,
+                                &outgoing_clone,
+                                &ctx,
+                                "Malformed packet".to_string(),
+                            )
+                            .await;
+                            break 'outer;
+                        }
+                    };
 
+                    let version = *protocol.read().await;
                     let packet = match *state.read().await {
-                        ConnectionState::Handshake => packets::serverbound::decode_handshaking(
-                            u32::from(id) as u8,
-                            data.to_vec(),
-                        ),
+                        ConnectionState::Handshake => {
+                            packets::serverbound::decode_handshaking(version, id, &data)
+                        }
                         ConnectionState::Status => {
-                            packets::serverbound::decode_status(u32::from(id) as u8, data.to_vec())
+                            packets::serverbound::decode_status(version, id, &data)
                         }
                         ConnectionState::Login => {
-                            packets::serverbound::decode_login(u32::from(id) as u8, data.to_vec())
+                            packets::serverbound::decode_login(version, id, &data)
+                        }
+                        ConnectionState::Play => {
+                            packets::serverbound::decode_play(version, id, &data)
+                        }
+                    };
+                    metrics.record(
+                        Direction::Serverbound,
+                        &*state.read().await,
+                        u32::from(id),
+                        data.len() + v32::byte_size(u32::from(id)),
+                        wire_bytes,
+                        started.elapsed(),
+                    );
+                    capture.record(
+                        Direction::Serverbound,
+                        &*state.read().await,
+                        version,
+                        u32::from(id),
+                        &data,
+                    );
+
+                    let packet = match packet {
+                        Ok(packet) => {
+                            debug!(
+                                "Received packet: id {} size {}",
+                                id,
+                                data.len() + v32::byte_size(u32::from(id))
+                            );
+                            packet
+                        }
+                        Err(PacketError {
+                            kind: PacketErrorKind::UnknownId,
+                            ..
+                        }) if *state.read().await == ConnectionState::Play => {
+                            // Most of Play isn't implemented yet
+                            debug!(
+                                "Unhandled packet: id {} size {}",
+                                id,
+                                data.len() + v32::byte_size(u32::from(id))
+                            );
+                            continue;
+                        }
+                        Err(PacketError {
+                            kind: PacketErrorKind::UnknownId,
+                            ..
+                        }) => {
+                            warn!("Unknown packet from {}: id {}", address, id);
+                            disconnect(&state, &outgoing_clone, &ctx, "Invalid packet".to_string())
+                                .await;
+                            break 'outer;
+                        }
+                        Err(e) => {
+                            warn!("Failed to decode packet from {}: {}", address, e);
+                            disconnect(&state, &outgoing_clone, &ctx, "Invalid packet".to_string())
+                                .await;
+                            break 'outer;
                         }
-                        _ => None,
                     };
 
-                    if packet.is_none() {
-                        error!(
-                            "Unknown packet: id {} size {}",
-                            u32::from(id),
-                            packet_bytes.len()
-                        );
-                        continue;
-                    } else {
-                        debug!(
-                            "Received packet: id {} size {}",
-                            u32::from(id),
-                            packet_bytes.len()
-                        );
-                    }
+                    // Answers to our keep-alives, only the resulting latency goes to the server
+                    let packet = match packet {
+                        Packets::ServerboundPlayKeepAlive(packet) => {
+                            match keep_alive.received(packet.id) {
+                                Some(latency) => {
+                                    let latency =
+                                        packets::internal::server_packets::Latency { latency };
+                                    inbound.send(Packets::from(latency)).await.unwrap();
+                                    continue;
+                                }
+                                None => {
+                                    warn!("Client at {} sent an unexpected keep-alive", address);
+                                    disconnect(
+                                        &state,
+                                        &outgoing_clone,
+                                        &ctx,
+                                        "Timed out".to_string(),
+                                    )
+                                    .await;
+                                    break 'outer;
+                                }
+                            }
+                        }
+                        packet => packet,
+                    };
 
-                    let packet = packet.unwrap();
+                    let encrypted = login.decryptor.is_some();
+                    let handled = process_packet(
+                        packet,
+                        &mut state,
+                        &protocol,
+                        &outgoing_clone,
+                        &ctx,
+                        &online,
+                        &mut login,
+                    )
+                    .await;
+
+                    // Anything the client sent after EncryptionResponse was already encrypted
+                    if !encrypted {
+                        if let Some(decryptor) = &mut login.decryptor {
+                            decryptor.decrypt(frames.pending_mut());
+                        }
+                    }
 
-                    if let Some(packet) =
-                        process_packet(packet, &mut state, &outgoing_clone, &ctx, players).await
-                    {
-                        inbound.send(packet).await.unwrap();
+                    match handled {
+                        Handled::Done => {}
+                        Handled::Forward(packet) => {
+                            forward(packet, &state, &inbound, &capture, &mut logged_in_sender)
+                                .await;
+                        }
+                        // Each Disconnect also closes the connection, so one is enough
+                        Handled::Close => break 'outer,
                     }
                 }
             }
@@ -367,8 +481,14 @@ impl Connection {
                 writer,
                 reader,
             },
-            ServerConnection { incoming, outgoing },
+            ServerConnection {
+                incoming,
+                outgoing,
+                capture,
+                metrics,
+            },
             crx,
+            logged_in,
         )
     }
 
@@ -389,80 +509,76 @@ impl Connection {
     }
 }
 
-// Returns true if the packet should be sent to the server
 async fn process_packet(
     packet: Packets,
     state: &mut Arc<RwLock<ConnectionState>>,
+    protocol: &Arc<RwLock<u32>>,
     outgoing: &Sender<Packets>,
     close_sender: &broadcast::Sender<String>,
-    players: usize,
-) -> Option<Packets> {
+    online: &OnlinePlayers,
+    login: &mut LoginState,
+) -> Handled {
     match packet {
         Packets::ServerboundHandshakingHandshake(packet) => {
             let ver = u32::from(packet.protocol_version);
             debug!("Client connected with protocol version {}", ver);
-            if ver > PROTOCOL_VERSION && packet.next_state != 1 {
-                warn!(
-                    "Client attempted connection with Unsupported Protocol Version {}",
-                    u32::from(packet.protocol_version)
-                );
-                close_sender.send("".to_string()).unwrap();
-            }
-
-            *state.write().await = packet.next_state.into();
-        }
-        Packets::ServerboundStatusRequest(_) => {
-            #[derive(serde::Serialize)]
-            struct StatusResponse {
-                version: Version,
-                players: Players,
-                description: Chat,
-
-                #[serde(skip_serializing_if = "Option::is_none")]
-                favicon: Option<String>,
-            }
-
-            #[derive(serde::Serialize)]
-            struct Version {
-                name: String,
-                protocol: i32,
-            }
 
-            #[derive(serde::Serialize)]
-            struct Players {
-                max: usize,
-                online: usize,
-                sample: Vec<Player>,
-            }
+            let next = match ConnectionState::try_from(packet.next_state) {
+                Ok(next @ (ConnectionState::Status | ConnectionState::Login)) => next,
+                _ => {
+                    warn!("Client requested invalid state {}", packet.next_state);
+                    close_sender.send("".to_string()).unwrap();
+                    return Handled::Close;
+                }
+            };
+            let logging_in = next == ConnectionState::Login;
+            *state.write().await = next;
 
-            #[derive(serde::Serialize)]
-            struct Player {
-                name: String,
-                id: String,
+            let server_address = String::from(packet.server_address);
+            if logging_in && CONFIG.network.forwarding.mode == ForwardingMode::Bungeecord {
+                match forwarding::parse_bungeecord(&server_address) {
+                    Ok(forwarded) => login.forwarded = Some(forwarded),
+                    Err(e) => {
+                        warn!("Client at {} wasn't forwarded: {}", login.address, e);
+                        disconnect_login(
+                            outgoing,
+                            "If you wish to use IP forwarding, please enable it in your BungeeCord config as well!",
+                        )
+                        .await;
+                        return Handled::Close;
+                    }
+                }
+            } else if server_address.chars().count() > 255 {
+                warn!("Client sent an oversized server address");
+                close_sender.send("".to_string()).unwrap();
+                return Handled::Close;
             }
 
-            #[derive(serde::Serialize)]
-            struct Chat {
-                text: String,
+            match ProtocolVersion::lookup(ver) {
+                Some(version) => *protocol.write().await = version.protocol,
+                // Status still gets answered, the client shows the version it should be using
+                None if *state.read().await == ConnectionState::Login => {
+                    warn!(
+                        "Client attempted connection with Unsupported Protocol Version {}",
+                        ver
+                    );
+                    disconnect_login(
+                        outgoing,
+                        &format!(
+                            "Unsupported client version! Please use {}",
+                            ProtocolVersion::supported_range()
+                        ),
+                    )
+                    .await;
+                    // The LoginStart that usually follows in the same read mustn't be handled
+                    return Handled::Close;
+                }
+                None => {}
             }
-
-            let response = StatusResponse {
-                version: Version {
-                    name: "1.16.5".to_string(),
-                    protocol: 754,
-                },
-                players: Players {
-                    max: CONFIG.network.max_players,
-                    online: players,
-                    sample: Vec::new(), // TODO
-                },
-                description: Chat {
-                    text: CONFIG.server.motd.clone(),
-                },
-                favicon: None,
-            };
-
-            let response = serde_json::to_string(&response).unwrap();
+        }
+        Packets::ServerboundStatusRequest(_) => {
+            let version = ProtocolVersion::lookup(*protocol.read().await).unwrap();
+            let response = StatusResponse::new(&online.players().await, version).into_json();
 
             outgoing
                 .send(Packets::from(
@@ -488,31 +604,320 @@ async fn process_packet(
             );
             let name = packet.name.to_string();
 
+            match CONFIG.network.forwarding.mode {
+                ForwardingMode::Bungeecord => {
+                    return match login.forwarded.take() {
+                        Some(forwarded) => {
+                            finish_login(
+                                outgoing,
+                                online,
+                                forwarded.uuid,
+                                name,
+                                SocketAddr::new(forwarded.address, login.address.port()),
+                                forwarded.properties,
+                            )
+                            .await
+                        }
+                        // Already disconnected when the Handshake wasn't forwarded
+                        None => Handled::Close,
+                    };
+                }
+                ForwardingMode::Velocity => {
+                    // The proxy answers with the player's real identity
+                    let plugins = login.plugins.clone();
+                    let outgoing = outgoing.clone();
+                    let online = online.clone();
+                    let address = login.address;
+                    login.tasks.push(Box::pin(async move {
+                        let response = plugins
+                            .request(
+                                Identifier::from(forwarding::VELOCITY_CHANNEL),
+                                forwarding::velocity_request(),
+                                login_plugin::DEFAULT_TIMEOUT,
+                            )
+                            .await;
+                        velocity_login(&outgoing, &online, address, name, response).await
+                    }));
+                    return Handled::Done;
+                }
+                ForwardingMode::None => {}
+            }
+
+            if !CONFIG.server.online_mode {
+                return finish_login(
+                    outgoing,
+                    online,
+                    Uuid::offline(&name),
+                    name,
+                    login.address,
+                    Vec::new(),
+                )
+                .await;
+            }
+
+            login.username = name;
+            login.verify_token = encryption::verify_token();
+
+            let public_key = SERVER_KEY.public_key_der.clone();
             outgoing
                 .send(Packets::from(
-                    packets::clientbound::login_packets::SetCompression {
-                        threshold: v32::from(CONFIG.network.advanced.compression_threshold),
+                    packets::clientbound::login_packets::EncryptionRequest {
+                        server_id: BoundedString::<20>::from(""),
+                        public_key,
+                        verify_token: login.verify_token.to_vec(),
                     },
                 ))
                 .await
                 .unwrap();
+        }
+        Packets::ServerboundLoginEncryptionResponse(packet) => {
+            if login.username.is_empty() || login.decryptor.is_some() {
+                disconnect_login(outgoing, "Unexpected encryption response").await;
+                return Handled::Close;
+            }
+
+            let shared_secret = SERVER_KEY.decrypt(&packet.shared_secret);
+            let verify_token = SERVER_KEY.decrypt(&packet.verify_token);
+
+            let (shared_secret, decryptor) = match (shared_secret, verify_token) {
+                (Some(shared_secret), Some(verify_token)) if verify_token == login.verify_token => {
+                    match Decryptor::new(&shared_secret) {
+                        Some(decryptor) => (shared_secret, decryptor),
+                        None => {
+                            disconnect_login(outgoing, "Invalid shared secret").await;
+                            return Handled::Close;
+                        }
+                    }
+                }
+                _ => {
+                    warn!("Client '{}' failed encryption handshake", login.username);
+                    disconnect_login(outgoing, "Invalid verify token").await;
+                    return Handled::Close;
+                }
+            };
+
+            // Both halves switch to AES/CFB8 from here on
+            login.decryptor = Some(decryptor);
             outgoing
                 .send(Packets::from(
-                    packets::clientbound::login_packets::LoginSuccess {
-                        uuid: BoundedString::<36>::from("00000000-0000-0000-0000-000000000000"),
-                        username: BoundedString::<16>::from(name.clone()),
+                    packets::internal::network_packets::EnableEncryption {
+                        shared_secret: shared_secret.clone(),
                     },
                 ))
                 .await
                 .unwrap();
-            return Some(Packets::from(
-                packets::internal::server_packets::Initalize {
-                    uuid: "00000000-0000-0000-0000-000000000000".to_string(),
-                    username: name,
-                },
-            ));
+
+            // Run alongside the socket, so the login deadline still applies while the session server answers
+            let hash = encryption::server_hash("", &shared_secret, &SERVER_KEY.public_key_der);
+            let outgoing = outgoing.clone();
+            let online = online.clone();
+            let address = login.address;
+            let username = login.username.clone();
+            login.tasks.push(Box::pin(async move {
+                session_login(&outgoing, &online, address, username, hash).await
+            }));
+        }
+        Packets::ServerboundLoginPluginResponse(packet) => {
+            if !login
+                .plugins
+                .respond(packet.message_id, packet.successful, packet.data)
+            {
+                warn!(
+                    "Client at {} answered unknown plugin request {}",
+                    login.address, packet.message_id
+                );
+                disconnect_login(outgoing, "Unexpected custom data from client").await;
+                return Handled::Close;
+            }
+        }
+        packet => return Handled::Forward(packet),
+    }
+    Handled::Done
+}
+
+// Passes a packet on to the server, switching to Play once the client has logged in
+async fn forward(
+    packet: Packets,
+    state: &Arc<RwLock<ConnectionState>>,
+    inbound: &Sender<Packets>,
+    capture: &Capture,
+    logged_in: &mut Option<oneshot::Sender<()>>,
+) {
+    if let Packets::InternalServerInitalize(packet) = &packet {
+        capture.logged_in(&packet.username);
+        *state.write().await = ConnectionState::Play;
+        if let Some(sender) = logged_in.take() {
+            let _ = sender.send(());
+        }
+    }
+    inbound.send(packet).await.unwrap();
+}
+
+// Finishes an online mode login once the session server has verified the player
+async fn session_login(
+    outgoing: &Sender<Packets>,
+    online: &OnlinePlayers,
+    address: SocketAddr,
+    username: String,
+    hash: String,
+) -> Handled {
+    match encryption::has_joined(&username, &hash).await {
+        Ok(Some(profile)) => match Uuid::parse(&profile.id) {
+            Some(uuid) => {
+                return finish_login(
+                    outgoing,
+                    online,
+                    uuid,
+                    profile.name,
+                    address,
+                    profile.properties,
+                )
+                .await
+            }
+            None => {
+                error!("Session server returned invalid UUID '{}'", profile.id);
+                disconnect_login(outgoing, "Failed to verify username!").await;
+            }
+        },
+        Ok(None) => {
+            warn!("Client '{}' failed session verification", username);
+            disconnect_login(outgoing, "Failed to verify username!").await;
+        }
+        Err(e) => {
+            error!("Error contacting session server: {}", e);
+            disconnect_login(outgoing, "Authentication servers are down").await;
+        }
+    }
+    Handled::Close
+}
+
+// Finishes a login forwarded by Velocity once the proxy has answered the player info request
+async fn velocity_login(
+    outgoing: &Sender<Packets>,
+    online: &OnlinePlayers,
+    address: SocketAddr,
+    username: String,
+    response: Result<Option<Vec<u8>>, PluginError>,
+) -> Handled {
+    let data = match response {
+        Ok(Some(data)) => data,
+        Ok(None) => {
+            disconnect_login(
+                outgoing,
+                "This server requires you to connect with Velocity.",
+            )
+            .await;
+            return Handled::Close;
+        }
+        Err(e) => {
+            warn!("Client at {} failed Velocity forwarding: {}", address, e);
+            disconnect_login(outgoing, "Unable to verify player details").await;
+            return Handled::Close;
         }
-        packet => return Some(packet),
+    };
+
+    match forwarding::parse_velocity(&data, &CONFIG.network.forwarding.secret) {
+        Ok(forwarded) => {
+            finish_login(
+                outgoing,
+                online,
+                forwarded.uuid,
+                forwarded.username.unwrap_or(username),
+                SocketAddr::new(forwarded.address, address.port()),
+                forwarded.properties,
+            )
+            .await
+        }
+        Err(e) => {
+            warn!("Client at {} failed Velocity forwarding: {}", address, e);
+            disconnect_login(outgoing, "Unable to verify player details").await;
+            Handled::Close
+        }
+    }
+}
+
+// Closes the connection from the reader, telling the client why if the current state allows it
+async fn disconnect(
+    state: &Arc<RwLock<ConnectionState>>,
+    outgoing: &Sender<Packets>,
+    close_sender: &broadcast::Sender<String>,
+    reason: String,
+) {
+    if *state.read().await == ConnectionState::Login {
+        disconnect_login(outgoing, &reason).await;
+    } else {
+        close_sender.send(reason).unwrap();
+    }
+}
+
+async fn disconnect_login(outgoing: &Sender<Packets>, reason: &str) {
+    outgoing
+        .send(Packets::from(
+            packets::clientbound::login_packets::Disconnect {
+                reason: Chat::from(serde_json::json!({ "text": reason }).to_string()),
+            },
+        ))
+        .await
+        .unwrap();
+}
+
+// Ops can always join, everyone else only while there are unreserved slots left
+fn take_slot(online: &OnlinePlayers, uuid: Uuid, username: &str) -> bool {
+    let config = &CONFIG.network;
+    let limit = match access::is_op(uuid, username) {
+        true => None,
+        false => Some(config.max_players.saturating_sub(config.reserved_slots)),
+    };
+    online.take_slot(limit)
+}
+
+// Enables compression, sends LoginSuccess and forwards the packet that hands the player over to the server.
+// Disconnects the client instead if they're banned, not whitelisted or the server is full.
+async fn finish_login(
+    outgoing: &Sender<Packets>,
+    online: &OnlinePlayers,
+    uuid: Uuid,
+    username: String,
+    address: SocketAddr,
+    properties: Vec<Property>,
+) -> Handled {
+    if let Err(e) = access::check(uuid, &username, address.ip()) {
+        let reason = e.to_string().replace('\n', " ");
+        info!("Rejected {} from {}: {}", username, address, reason);
+        disconnect_login(outgoing, &e.to_string()).await;
+        return Handled::Close;
     }
-    None
+    if !take_slot(online, uuid, &username) {
+        info!("Rejected {}, the server is full", username);
+        disconnect_login(outgoing, &CONFIG.network.full_message).await;
+        return Handled::Close;
+    }
+
+    if let Some(threshold) = compression::threshold() {
+        outgoing
+            .send(Packets::from(
+                packets::clientbound::login_packets::SetCompression {
+                    threshold: v32::from(threshold as u32),
+                },
+            ))
+            .await
+            .unwrap();
+    }
+    outgoing
+        .send(Packets::from(
+            packets::clientbound::login_packets::LoginSuccess {
+                uuid,
+                username: BoundedString::<16>::from(username.clone()),
+            },
+        ))
+        .await
+        .unwrap();
+    Handled::Forward(Packets::from(
+        packets::internal::server_packets::Initalize {
+            uuid,
+            username,
+            address,
+            properties,
+        },
+    ))
 }
@@ -0,0 +1,187 @@
+use std::time::Duration;
+
+use aes::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use lazy_static::lazy_static;
+use rsa::{pkcs8::EncodePublicKey, PaddingScheme, RsaPrivateKey, RsaPublicKey};
+use sha1::{Digest, Sha1};
+
+use crate::{config::CONFIG, packets::types::Property};
+
+type Aes128Cfb8Enc = cfb8::Encryptor<aes::Aes128>;
+type Aes128Cfb8Dec = cfb8::Decryptor<aes::Aes128>;
+
+// Longest a login waits on the session server
+const SESSION_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Generated once and shared by every connection, the same as vanilla.
+lazy_static! {
+    pub static ref SERVER_KEY: ServerKey = ServerKey::new();
+    // Shared so connections to the session server are reused between logins
+    static ref HTTP_CLIENT: reqwest::Client = reqwest::Client::builder()
+        .timeout(SESSION_TIMEOUT)
+        .build()
+        .expect("Failed to create HTTP client");
+}
+
+pub struct ServerKey {
+    private_key: RsaPrivateKey,
+    pub public_key_der: Vec<u8>,
+}
+
+impl ServerKey {
+    fn new() -> Self {
+        let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), 1024)
+            .expect("Failed to generate server keypair");
+        let public_key_der = RsaPublicKey::from(&private_key)
+            .to_public_key_der()
+            .expect("Failed to encode server public key")
+            .as_ref()
+            .to_vec();
+
+        Self {
+            private_key,
+            public_key_der,
+        }
+    }
+
+    pub fn decrypt(&self, data: &[u8]) -> Option<Vec<u8>> {
+        self.private_key
+            .decrypt(PaddingScheme::new_pkcs1v15_encrypt(), data)
+            .ok()
+    }
+}
+
+pub fn verify_token() -> [u8; 4] {
+    rand::random()
+}
+
+/// AES-128-CFB8 stream cipher. The shared secret is used as both key and IV.
+pub struct Encryptor(Aes128Cfb8Enc);
+
+impl Encryptor {
+    pub fn new(shared_secret: &[u8]) -> Option<Self> {
+        Aes128Cfb8Enc::new_from_slices(shared_secret, shared_secret)
+            .ok()
+            .map(Self)
+    }
+
+    pub fn encrypt(&mut self, data: &mut [u8]) {
+        for block in data.chunks_mut(1) {
+            self.0.encrypt_block_mut(block.into());
+        }
+    }
+}
+
+pub struct Decryptor(Aes128Cfb8Dec);
+
+impl Decryptor {
+    pub fn new(shared_secret: &[u8]) -> Option<Self> {
+        Aes128Cfb8Dec::new_from_slices(shared_secret, shared_secret)
+            .ok()
+            .map(Self)
+    }
+
+    pub fn decrypt(&mut self, data: &mut [u8]) {
+        for block in data.chunks_mut(1) {
+            self.0.decrypt_block_mut(block.into());
+        }
+    }
+}
+
+/// Minecraft's non-standard SHA-1 hex digest, the hash is treated as a signed big-endian integer.
+pub fn server_hash(server_id: &str, shared_secret: &[u8], public_key_der: &[u8]) -> String {
+    let mut hash: [u8; 20] = Sha1::new()
+        .chain_update(server_id.as_bytes())
+        .chain_update(shared_secret)
+        .chain_update(public_key_der)
+        .finalize()
+        .into();
+
+    let negative = hash[0] & 0x80 != 0;
+    if negative {
+        // Two's complement
+        let mut carry = true;
+        for byte in hash.iter_mut().rev() {
+            *byte = !*byte;
+            if carry {
+                let (value, overflow) = byte.overflowing_add(1);
+                *byte = value;
+                carry = overflow;
+            }
+        }
+    }
+
+    let digest = hash
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+    let digest = digest.trim_start_matches('0');
+
+    if negative {
+        format!("-{}", digest)
+    } else {
+        digest.to_string()
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct GameProfile {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub properties: Vec<Property>,
+}
+
+/// Asks the session server whether `username` has joined with `server_hash`.
+/// Returns `Ok(None)` if the session server doesn't know about the player.
+pub async fn has_joined(
+    username: &str,
+    server_hash: &str,
+) -> Result<Option<GameProfile>, reqwest::Error> {
+    let url = format!(
+        "{}/session/minecraft/hasJoined",
+        CONFIG.network.advanced.session_server.trim_end_matches('/')
+    );
+
+    let response = HTTP_CLIENT
+        .get(url)
+        .query(&[("username", username), ("serverId", server_hash)])
+        .send()
+        .await?
+        .error_for_status()?;
+
+    if response.status() == reqwest::StatusCode::NO_CONTENT {
+        return Ok(None);
+    }
+    Ok(Some(response.json().await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The usual examples for Minecraft's signed hex digests. The name stands in for the whole hash input
+    #[test]
+    fn server_hash_positive() {
+        assert_eq!(
+            server_hash("Notch", &[], &[]),
+            "4ed1f46bbe04bc756bcb17c0c7ce3e4632f06a48"
+        );
+    }
+
+    #[test]
+    fn server_hash_negative() {
+        assert_eq!(
+            server_hash("jeb_", &[], &[]),
+            "-7c9d5b0044c130109a5d7b5fb5c317c02b4e28c1"
+        );
+    }
+
+    #[test]
+    fn server_hash_leading_zero() {
+        assert_eq!(
+            server_hash("simon", &[], &[]),
+            "88e16a1019277b15d58faf0541e11910eb756f6"
+        );
+    }
+}
@@ -0,0 +1,237 @@
+use std::net::IpAddr;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::packets::{
+    serial::{self, Decode},
+    types::{v32, BoundedString, Property, Uuid},
+};
+
+pub const VELOCITY_CHANNEL: &str = "velocity:player_info";
+// Newer versions only add fields for 1.19+ chat signing, which isn't supported
+const VELOCITY_FORWARDING_VERSION: u8 = 1;
+
+/// A player's identity, as forwarded by a BungeeCord or Velocity proxy
+pub struct ForwardedPlayer {
+    pub address: IpAddr,
+    pub uuid: Uuid,
+    // Only Velocity forwards the username, BungeeCord leaves it to LoginStart
+    pub username: Option<String>,
+    pub properties: Vec<Property>,
+}
+
+#[derive(Debug)]
+pub enum ForwardingError {
+    InvalidSignature,
+    Malformed,
+    MissingSecret,
+}
+
+impl std::fmt::Display for ForwardingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ForwardingError::InvalidSignature => write!(f, "Invalid forwarding signature"),
+            ForwardingError::Malformed => write!(f, "Malformed forwarding data"),
+            ForwardingError::MissingSecret => write!(f, "No forwarding secret is configured"),
+        }
+    }
+}
+
+impl From<serial::DecodeError> for ForwardingError {
+    fn from(_: serial::DecodeError) -> Self {
+        ForwardingError::Malformed
+    }
+}
+
+/// BungeeCord's legacy forwarding packs the player into the Handshake's server address:
+/// `host\0address\0uuid` followed by `\0properties` as a JSON array if the proxy is in online mode.
+pub fn parse_bungeecord(server_address: &str) -> Result<ForwardedPlayer, ForwardingError> {
+    let mut parts = server_address.split('\0').skip(1);
+
+    let address = parts
+        .next()
+        .and_then(|address| address.parse().ok())
+        .ok_or(ForwardingError::Malformed)?;
+    let uuid = parts
+        .next()
+        .and_then(Uuid::parse)
+        .ok_or(ForwardingError::Malformed)?;
+    let properties = match parts.next() {
+        Some(json) => serde_json::from_str(json).map_err(|_| ForwardingError::Malformed)?,
+        None => Vec::new(),
+    };
+
+    Ok(ForwardedPlayer {
+        address,
+        uuid,
+        username: None,
+        properties,
+    })
+}
+
+/// Data for the `velocity:player_info` Login Plugin Request
+pub fn velocity_request() -> Vec<u8> {
+    vec![VELOCITY_FORWARDING_VERSION]
+}
+
+/// Verifies and parses Velocity's response to `velocity:player_info`.
+/// The payload is prefixed by its HMAC-SHA256, keyed with the forwarding `secret` shared with the proxy.
+pub fn parse_velocity(data: &[u8], secret: &str) -> Result<ForwardedPlayer, ForwardingError> {
+    if data.len() < 32 {
+        return Err(ForwardingError::Malformed);
+    }
+    let (signature, payload) = data.split_at(32);
+
+    // HMAC accepts an empty key, which would let anyone sign their own identity
+    if secret.is_empty() {
+        return Err(ForwardingError::MissingSecret);
+    }
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .map_err(|_| ForwardingError::InvalidSignature)?;
+    mac.update(payload);
+    mac.verify_slice(signature)
+        .map_err(|_| ForwardingError::InvalidSignature)?;
+
+    let mut decoder = serial::Decoder::new(payload);
+    if u32::from(v32::decode(&mut decoder)?) < VELOCITY_FORWARDING_VERSION as u32 {
+        return Err(ForwardingError::Malformed);
+    }
+
+    let address = BoundedString::<255>::decode(&mut decoder)?
+        .to_string()
+        .parse()
+        .map_err(|_| ForwardingError::Malformed)?;
+    let uuid = Uuid::decode(&mut decoder)?;
+    let username = BoundedString::<16>::decode(&mut decoder)?.to_string();
+
+    let count = u32::from(v32::decode(&mut decoder)?) as usize;
+    let mut properties = Vec::with_capacity(count.min(decoder.remaining()));
+    for _ in 0..count {
+        properties.push(Property::decode(&mut decoder)?);
+    }
+
+    Ok(ForwardedPlayer {
+        address,
+        uuid,
+        username: Some(username),
+        properties,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "hunter2";
+    const UUID: &str = "069a79f4-44e9-4726-a5be-fca90e38aaf5";
+
+    // Velocity's player info, without the signature
+    fn payload(version: u32) -> Vec<u8> {
+        let mut payload = serial::encode_to_vec(&v32::from(version)).unwrap();
+        payload.extend(serial::encode_to_vec(&BoundedString::<255>::from("192.168.0.1")).unwrap());
+        payload.extend(serial::encode_to_vec(&Uuid::parse(UUID).unwrap()).unwrap());
+        payload.extend(serial::encode_to_vec(&BoundedString::<16>::from("Notch")).unwrap());
+        payload.extend(serial::encode_to_vec(&v32::from(1)).unwrap());
+        let textures = Property {
+            name: "textures".to_string(),
+            value: "e30=".to_string(),
+            signature: None,
+        };
+        payload.extend(serial::encode_to_vec(&textures).unwrap());
+        payload
+    }
+
+    fn sign(payload: &[u8], secret: &str) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(payload);
+        let mut data = mac.finalize().into_bytes().to_vec();
+        data.extend(payload);
+        data
+    }
+
+    #[test]
+    fn velocity() {
+        let forwarded = parse_velocity(&sign(&payload(1), SECRET), SECRET).unwrap();
+        assert_eq!(forwarded.address, "192.168.0.1".parse::<IpAddr>().unwrap());
+        assert_eq!(forwarded.uuid.to_string(), UUID);
+        assert_eq!(forwarded.username.as_deref(), Some("Notch"));
+        assert_eq!(forwarded.properties.len(), 1);
+        assert_eq!(forwarded.properties[0].name, "textures");
+    }
+
+    #[test]
+    fn velocity_wrong_signature() {
+        assert!(matches!(
+            parse_velocity(&sign(&payload(1), "hunter3"), SECRET),
+            Err(ForwardingError::InvalidSignature)
+        ));
+
+        // Changing the payload after it was signed
+        let mut data = sign(&payload(1), SECRET);
+        *data.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            parse_velocity(&data, SECRET),
+            Err(ForwardingError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn velocity_empty_secret() {
+        assert!(matches!(
+            parse_velocity(&sign(&payload(1), ""), ""),
+            Err(ForwardingError::MissingSecret)
+        ));
+    }
+
+    #[test]
+    fn velocity_malformed() {
+        assert!(matches!(
+            parse_velocity(&[0; 31], SECRET),
+            Err(ForwardingError::Malformed)
+        ));
+        assert!(matches!(
+            parse_velocity(&sign(&payload(0), SECRET), SECRET),
+            Err(ForwardingError::Malformed)
+        ));
+
+        // Signed, but cut off before the properties
+        let payload = payload(1);
+        let truncated = sign(&payload[..payload.len() - 10], SECRET);
+        assert!(matches!(
+            parse_velocity(&truncated, SECRET),
+            Err(ForwardingError::Malformed)
+        ));
+    }
+
+    #[test]
+    fn bungeecord() {
+        let forwarded = parse_bungeecord(&format!("localhost\x00192.168.0.1\x00{}", UUID)).unwrap();
+        assert_eq!(forwarded.address, "192.168.0.1".parse::<IpAddr>().unwrap());
+        assert_eq!(forwarded.uuid.to_string(), UUID);
+        assert!(forwarded.username.is_none());
+        assert!(forwarded.properties.is_empty());
+    }
+
+    #[test]
+    fn bungeecord_properties() {
+        // Proxies in online mode forward the profile's properties, and leave out the UUID's hyphens
+        let forwarded = parse_bungeecord(
+            "localhost\x002001:db8::1\x00069a79f444e94726a5befca90e38aaf5\x00\
+             [{\"name\":\"textures\",\"value\":\"e30=\",\"signature\":\"c2ln\"}]",
+        )
+        .unwrap();
+        assert_eq!(forwarded.address, "2001:db8::1".parse::<IpAddr>().unwrap());
+        assert_eq!(forwarded.uuid.to_string(), UUID);
+        assert_eq!(forwarded.properties.len(), 1);
+        assert_eq!(forwarded.properties[0].signature.as_deref(), Some("c2ln"));
+    }
+
+    #[test]
+    fn bungeecord_malformed() {
+        assert!(parse_bungeecord("localhost").is_err());
+        assert!(parse_bungeecord(&format!("localhost\x00not an address\x00{}", UUID)).is_err());
+        assert!(parse_bungeecord("localhost\x00192.168.0.1\x00not a uuid").is_err());
+        assert!(parse_bungeecord(&format!("localhost\x00192.168.0.1\x00{}\x00{{", UUID)).is_err());
+    }
+}
@@ -0,0 +1,204 @@
+use crate::packets::{serial, types::v32};
+
+use super::compression::{self, Inflater};
+
+// Largest length a 3 byte VarInt can hold, same limit as vanilla
+pub const MAX_FRAME_LENGTH: usize = 2097151;
+
+pub enum Frame {
+    Complete(Vec<u8>),
+    // Not enough bytes have been buffered yet to produce a full frame
+    Incomplete,
+}
+
+#[derive(Debug)]
+pub enum FrameError {
+    InvalidLength,
+    TooLarge(usize),
+}
+
+impl std::fmt::Display for FrameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameError::InvalidLength => write!(f, "Invalid frame length"),
+            FrameError::TooLarge(len) => write!(f, "Frame too large ({} bytes)", len),
+        }
+    }
+}
+
+/// Splits a stream of bytes into length-prefixed frames.
+/// Bytes are accumulated until a full frame is available, so frames may be split across any number of reads.
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+    // Start of the bytes that haven't been consumed yet
+    offset: usize,
+}
+
+impl FrameDecoder {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            buffer: Vec::with_capacity(capacity),
+            offset: 0,
+        }
+    }
+
+    pub fn extend(&mut self, data: &[u8]) {
+        // Reclaim the consumed bytes before growing the buffer
+        if self.offset > 0 {
+            self.buffer.drain(..self.offset);
+            self.offset = 0;
+        }
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Bytes that have been buffered but not yet returned as part of a frame
+    pub fn pending_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer[self.offset..]
+    }
+
+    pub fn next_frame(&mut self) -> Result<Frame, FrameError> {
+        let pending = &self.buffer[self.offset..];
+
+        let (length, lsize) = match serial::decode_from_slice::<v32>(pending) {
+            Ok(result) => result,
+            // A valid length prefix is at most 3 bytes
+            Err(serial::DecodeError::NotEnoughBytes) if pending.len() < 3 => {
+                return Ok(Frame::Incomplete)
+            }
+            Err(_) => return Err(FrameError::InvalidLength),
+        };
+        // Oversized lengths need more than 3 bytes, so they're checked first to report them as such
+        let length = u32::from(length) as usize;
+        if length > MAX_FRAME_LENGTH {
+            return Err(FrameError::TooLarge(length));
+        }
+        if lsize > 3 {
+            return Err(FrameError::InvalidLength);
+        }
+        if length == 0 {
+            return Err(FrameError::InvalidLength);
+        }
+
+        if pending.len() < lsize + length {
+            return Ok(Frame::Incomplete);
+        }
+
+        let frame = pending[lsize..lsize + length].to_vec();
+        self.offset += lsize + length;
+        if self.offset == self.buffer.len() {
+            self.buffer.clear();
+            self.offset = 0;
+        }
+
+        Ok(Frame::Complete(frame))
+    }
+}
+
+// Splits a frame into its packet id and data, inflating it first if compression is enabled
+pub fn unpack_frame(
+    mut frame: Vec<u8>,
+    inflater: Option<&mut Inflater>,
+) -> Result<(v32, Vec<u8>), serial::DecodeError> {
+    if let Some(inflater) = inflater {
+        let (data_length, lsize) = serial::decode_from_slice::<v32>(&frame)?;
+        let data_length = u32::from(data_length) as usize;
+        frame = if data_length > 0 {
+            // Packets below the threshold must be sent uncompressed
+            if compression::threshold().is_none_or(|threshold| data_length < threshold) {
+                return Err(serial::DecodeError::InvalidData);
+            }
+            inflater.decompress(&frame[lsize..], data_length)?
+        } else {
+            frame.split_off(lsize)
+        };
+    }
+
+    let (id, id_size) = serial::decode_from_slice::<v32>(&frame)?;
+    Ok((id, frame.split_off(id_size)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn complete(decoder: &mut FrameDecoder) -> Vec<u8> {
+        match decoder.next_frame() {
+            Ok(Frame::Complete(frame)) => frame,
+            Ok(Frame::Incomplete) => panic!("Expected a complete frame"),
+            Err(e) => panic!("Expected a complete frame, got {}", e),
+        }
+    }
+
+    fn incomplete(decoder: &mut FrameDecoder) -> bool {
+        matches!(decoder.next_frame(), Ok(Frame::Incomplete))
+    }
+
+    #[test]
+    fn length_split_across_reads() {
+        // 300 byte frame, its length takes 2 bytes
+        let mut data = vec![0xAC, 0x02];
+        data.extend([7; 300]);
+
+        let mut decoder = FrameDecoder::with_capacity(16);
+        decoder.extend(&data[..1]);
+        assert!(incomplete(&mut decoder));
+        decoder.extend(&data[1..100]);
+        assert!(incomplete(&mut decoder));
+        decoder.extend(&data[100..]);
+        assert_eq!(complete(&mut decoder), vec![7; 300]);
+        assert!(incomplete(&mut decoder));
+    }
+
+    #[test]
+    fn several_frames_in_one_read() {
+        let mut decoder = FrameDecoder::with_capacity(16);
+        decoder.extend(&[1, 0xAA, 2, 0xBB, 0xCC, 3, 0xDD]);
+        assert_eq!(complete(&mut decoder), vec![0xAA]);
+        assert_eq!(complete(&mut decoder), vec![0xBB, 0xCC]);
+        // The last frame is still missing 2 bytes
+        assert!(incomplete(&mut decoder));
+        assert_eq!(decoder.pending_mut(), &[3, 0xDD]);
+
+        decoder.extend(&[0xEE, 0xFF]);
+        assert_eq!(complete(&mut decoder), vec![0xDD, 0xEE, 0xFF]);
+    }
+
+    #[test]
+    fn length_longer_than_3_bytes() {
+        // A length of 1, padded out to 4 bytes
+        let mut decoder = FrameDecoder::with_capacity(16);
+        decoder.extend(&[0x81, 0x80, 0x80, 0x00, 0xAA]);
+        assert!(matches!(
+            decoder.next_frame(),
+            Err(FrameError::InvalidLength)
+        ));
+
+        // 3 bytes in and the length still isn't finished
+        let mut decoder = FrameDecoder::with_capacity(16);
+        decoder.extend(&[0x80, 0x80, 0x80]);
+        assert!(matches!(
+            decoder.next_frame(),
+            Err(FrameError::InvalidLength)
+        ));
+    }
+
+    #[test]
+    fn too_large() {
+        let mut decoder = FrameDecoder::with_capacity(16);
+        decoder.extend(&[0x80, 0x80, 0x80, 0x01]);
+        assert!(matches!(
+            decoder.next_frame(),
+            Err(FrameError::TooLarge(length)) if length == MAX_FRAME_LENGTH + 1
+        ));
+    }
+
+    #[test]
+    fn zero_length() {
+        let mut decoder = FrameDecoder::with_capacity(16);
+        decoder.extend(&[0x00]);
+        assert!(matches!(
+            decoder.next_frame(),
+            Err(FrameError::InvalidLength)
+        ));
+    }
+}
@@ -0,0 +1,59 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use tokio::time::{Interval, MissedTickBehavior};
+
+// Same as vanilla. A client that hasn't answered by the next keep-alive has timed out
+pub const INTERVAL: Duration = Duration::from_secs(15);
+
+/// Sends Play keep-alives and measures the round trip. Owned by the reader task.
+pub struct KeepAlive {
+    interval: Interval,
+    // Id and send time of the keep-alive the client hasn't answered yet
+    pending: Option<(i64, Instant)>,
+    // Smoothed round trip in milliseconds, shown as the player's ping in the tab list
+    latency: u32,
+}
+
+impl KeepAlive {
+    pub fn new() -> Self {
+        let mut interval =
+            tokio::time::interval_at(tokio::time::Instant::now() + INTERVAL, INTERVAL);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Self {
+            interval,
+            pending: None,
+            latency: 0,
+        }
+    }
+
+    pub async fn tick(&mut self) {
+        self.interval.tick().await;
+    }
+
+    /// Starts a new keep-alive, returning its id. None if the last one was never answered
+    pub fn send(&mut self) -> Option<i64> {
+        if self.pending.is_some() {
+            return None;
+        }
+
+        // Vanilla uses the current time as the id
+        let id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_millis() as i64);
+        self.pending = Some((id, Instant::now()));
+        Some(id)
+    }
+
+    /// Handles the client echoing `id`, returning the updated latency. None if it wasn't the id we sent
+    pub fn received(&mut self, id: i64) -> Option<u32> {
+        match self.pending {
+            Some((expected, sent)) if expected == id => {
+                self.pending = None;
+                let rtt = sent.elapsed().as_millis().min(u32::MAX as u128) as u32;
+                self.latency = ((self.latency as u64 * 3 + rtt as u64) / 4) as u32;
+                Some(self.latency)
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use log::{error, info};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::Sender;
+use tokio::task::JoinHandle;
+
+use crate::config::CONFIG;
+
+// Same backlog as the standard library's TcpListener::bind
+const BACKLOG: i32 = 128;
+
+/// Any stream a client can connect over
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Stream for T {}
+
+pub type Incoming = (Box<dyn Stream>, SocketAddr);
+
+/// Parses `network.bind`. Entries are either a full socket address, or an IP that listens on `network.port`.
+fn bind_addresses() -> Vec<SocketAddr> {
+    CONFIG
+        .network
+        .bind
+        .iter()
+        .filter_map(|entry| {
+            let address = entry
+                .parse::<SocketAddr>()
+                .or_else(|_| {
+                    entry
+                        .parse::<IpAddr>()
+                        .map(|ip| SocketAddr::new(ip, CONFIG.network.port))
+                })
+                .ok();
+            if address.is_none() {
+                error!("Invalid bind address '{}'", entry);
+            }
+            address
+        })
+        .collect()
+}
+
+/// Binds a TCP listener. Binding to `::` accepts both IPv6 and IPv4 clients, any other IPv6 address is IPv6 only.
+fn bind_tcp(address: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    if address.is_ipv6() {
+        socket.set_only_v6(!address.ip().is_unspecified())?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(BACKLOG)?;
+
+    TcpListener::from_std(socket.into())
+}
+
+/// Accepts connections on `listener` until the receiving end of `accepted` is dropped
+fn spawn_tcp(listener: TcpListener, accepted: Sender<Incoming>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((socket, addr)) => {
+                    // Configure TCP Stream
+                    socket.set_nodelay(true).unwrap();
+
+                    // Dual-stack listeners see IPv4 clients as IPv4-mapped IPv6 addresses,
+                    // which the throttle wouldn't recognise as loopback or count with the same IP
+                    let addr = SocketAddr::new(addr.ip().to_canonical(), addr.port());
+
+                    if accepted.send((Box::new(socket), addr)).await.is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    error!("Error accepting connection: {}", e);
+                }
+            }
+        }
+    })
+}
+
+// Only ever deletes sockets, in case the path points at something else by mistake
+#[cfg(unix)]
+fn remove_socket(path: &str) -> std::io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path),
+        Ok(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            "Path exists and isn't a socket",
+        )),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(unix)]
+fn spawn_unix(path: &str, accepted: Sender<Incoming>) -> std::io::Result<JoinHandle<()>> {
+    // A socket file left over from an unclean shutdown would stop us from binding
+    remove_socket(path)?;
+    let listener = tokio::net::UnixListener::bind(path)?;
+
+    Ok(tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((socket, _)) => {
+                    // Unix sockets are only reachable from this machine
+                    let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0);
+                    if accepted.send((Box::new(socket), addr)).await.is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    error!("Error accepting connection: {}", e);
+                }
+            }
+        }
+    }))
+}
+
+/// Binds every address in `network.bind` and `network.unix_socket`, sending accepted connections to `accepted`
+pub fn start(accepted: Sender<Incoming>) -> Vec<JoinHandle<()>> {
+    let mut listeners = Vec::new();
+    for address in bind_addresses() {
+        match bind_tcp(address) {
+            Ok(listener) => {
+                info!("Listening on {}", address);
+                listeners.push(spawn_tcp(listener, accepted.clone()));
+            }
+            Err(e) => {
+                error!("Failed to bind to {}: {}", address, e);
+            }
+        }
+    }
+
+    let path = &CONFIG.network.unix_socket;
+    if !path.is_empty() {
+        #[cfg(unix)]
+        match spawn_unix(path, accepted) {
+            Ok(listener) => {
+                info!("Listening on {}", path);
+                listeners.push(listener);
+            }
+            Err(e) => {
+                error!("Failed to bind to {}: {}", path, e);
+            }
+        }
+        #[cfg(not(unix))]
+        error!("Unix sockets aren't supported on this platform");
+    }
+
+    if listeners.is_empty() {
+        error!("Not listening on any address, check network.bind in the config");
+    }
+    listeners
+}
+
+/// Removes the socket file created for `network.unix_socket`
+pub fn stop() {
+    let path = &CONFIG.network.unix_socket;
+    #[cfg(unix)]
+    if !path.is_empty() {
+        if let Err(e) = remove_socket(path) {
+            error!("Failed to remove {}: {}", path, e);
+        }
+    }
+}
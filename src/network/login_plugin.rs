@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc, Mutex,
+};
+use std::time::Duration;
+
+use tokio::sync::{mpsc::Sender, oneshot};
+
+use crate::packets::{
+    self,
+    types::{v32, Identifier},
+    Packets,
+};
+
+// How long a client gets to answer a request when the caller doesn't need anything specific
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Some(data) if the client understood the channel
+type Response = Option<Vec<u8>>;
+
+#[derive(Debug)]
+pub enum PluginError {
+    TimedOut,
+    // The connection closed before the client answered
+    Closed,
+}
+
+impl std::fmt::Display for PluginError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PluginError::TimedOut => write!(f, "Timed out waiting for plugin response"),
+            PluginError::Closed => write!(f, "Connection closed before plugin response"),
+        }
+    }
+}
+
+/// Sends Login Plugin Requests and matches the client's responses up by message id.
+/// Cheap to clone, so requests can be awaited outside the reader task, which passes responses in through `respond`.
+#[derive(Clone)]
+pub struct LoginPlugins {
+    outgoing: Sender<Packets>,
+    next_id: Arc<AtomicU32>,
+    pending: Arc<Mutex<HashMap<v32, oneshot::Sender<Response>>>>,
+}
+
+impl LoginPlugins {
+    pub fn new(outgoing: Sender<Packets>) -> Self {
+        Self {
+            outgoing,
+            next_id: Arc::new(AtomicU32::new(0)),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Sends `data` on `channel` and waits up to `timeout` for the answer.
+    /// Resolves to None if the client doesn't understand the channel, as vanilla clients do for everything.
+    pub async fn request(
+        &self,
+        channel: Identifier,
+        data: Vec<u8>,
+        timeout: Duration,
+    ) -> Result<Response, PluginError> {
+        let message_id = v32::from(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let (sender, receiver) = oneshot::channel();
+        self.pending.lock().unwrap().insert(message_id, sender);
+
+        self.outgoing
+            .send(Packets::from(
+                packets::clientbound::login_packets::PluginRequest {
+                    message_id,
+                    channel,
+                    data,
+                },
+            ))
+            .await
+            .map_err(|_| PluginError::Closed)?;
+
+        let result = tokio::time::timeout(timeout, receiver).await;
+        self.pending.lock().unwrap().remove(&message_id);
+        match result {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(_)) => Err(PluginError::Closed),
+            Err(_) => Err(PluginError::TimedOut),
+        }
+    }
+
+    /// Hands a Login Plugin Response to the request waiting on it. False if no request has that id
+    pub fn respond(&self, message_id: v32, successful: bool, data: Vec<u8>) -> bool {
+        match self.pending.lock().unwrap().remove(&message_id) {
+            Some(sender) => {
+                // The request may have just timed out
+                let _ = sender.send(successful.then_some(data));
+                true
+            }
+            None => false,
+        }
+    }
+}
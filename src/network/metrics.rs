@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::sync::{atomic::Ordering, Mutex};
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+
+use crate::packets::types::ConnectionState;
+
+use super::capture::{format::STATES, Direction};
+use super::rate_limit::{BYTE_LIMIT_HITS, PACKET_LIMIT_HITS};
+
+lazy_static! {
+    // Traffic of connections that have since closed
+    static ref CLOSED: Mutex<TrafficMetrics> = Mutex::new(TrafficMetrics::default());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PacketKey {
+    pub direction: Direction,
+    pub state: u8,
+    pub id: u32,
+}
+
+impl std::fmt::Display for PacketKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} 0x{:02X}",
+            self.direction, STATES[self.state as usize], self.id
+        )
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PacketStats {
+    pub count: u64,
+    // Id and fields, before compression
+    pub raw_bytes: u64,
+    // The whole frame as sent over the socket, after compression
+    pub wire_bytes: u64,
+    // Spent encoding, or decoding, the packet. Includes compression
+    pub time: Duration,
+}
+
+impl PacketStats {
+    /// Wire bytes per raw byte, lower is better. Can go above 1 from the frame headers
+    pub fn compression_ratio(&self) -> f64 {
+        match self.raw_bytes {
+            0 => 1.0,
+            raw => self.wire_bytes as f64 / raw as f64,
+        }
+    }
+
+    fn add(&mut self, other: &PacketStats) {
+        self.count += other.count;
+        self.raw_bytes += other.raw_bytes;
+        self.wire_bytes += other.wire_bytes;
+        self.time += other.time;
+    }
+}
+
+/// Packet counts and sizes, broken down by direction, state and packet id
+#[derive(Debug, Default, Clone)]
+pub struct TrafficMetrics {
+    packets: HashMap<PacketKey, PacketStats>,
+}
+
+impl TrafficMetrics {
+    pub fn iter(&self) -> impl Iterator<Item = (&PacketKey, &PacketStats)> {
+        self.packets.iter()
+    }
+
+    /// Everything sent in `direction`, across all packet types
+    pub fn total(&self, direction: Direction) -> PacketStats {
+        let mut total = PacketStats::default();
+        for (_, stats) in self.iter().filter(|(key, _)| key.direction == direction) {
+            total.add(stats);
+        }
+        total
+    }
+
+    pub fn merge(&mut self, other: &TrafficMetrics) {
+        for (key, stats) in other.iter() {
+            self.packets.entry(*key).or_default().add(stats);
+        }
+    }
+}
+
+/// A single connection's traffic, shared between its reader and writer and the server.
+/// Added to the server-wide total once the connection closes.
+#[derive(Default)]
+pub struct ConnectionMetrics {
+    traffic: Mutex<TrafficMetrics>,
+}
+
+impl ConnectionMetrics {
+    pub fn record(
+        &self,
+        direction: Direction,
+        state: &ConnectionState,
+        id: u32,
+        raw_bytes: usize,
+        wire_bytes: usize,
+        time: Duration,
+    ) {
+        let key = PacketKey {
+            direction,
+            state: u8::from(state),
+            id,
+        };
+        let mut traffic = self.traffic.lock().unwrap();
+        let stats = traffic.packets.entry(key).or_default();
+        stats.count += 1;
+        stats.raw_bytes += raw_bytes as u64;
+        stats.wire_bytes += wire_bytes as u64;
+        stats.time += time;
+    }
+
+    pub fn snapshot(&self) -> TrafficMetrics {
+        self.traffic.lock().unwrap().clone()
+    }
+}
+
+impl Drop for ConnectionMetrics {
+    fn drop(&mut self) {
+        CLOSED
+            .lock()
+            .unwrap()
+            .merge(self.traffic.get_mut().unwrap());
+    }
+}
+
+/// Server-wide network metrics
+pub struct NetworkMetrics {
+    pub traffic: TrafficMetrics,
+    // Clients kicked by the rate limiter
+    pub packet_limit_hits: u64,
+    pub byte_limit_hits: u64,
+}
+
+impl NetworkMetrics {
+    /// Combines the traffic of closed connections with that of `open` ones
+    pub fn collect<'a>(open: impl Iterator<Item = &'a ConnectionMetrics>) -> Self {
+        let mut traffic = CLOSED.lock().unwrap().clone();
+        for connection in open {
+            traffic.merge(&connection.traffic.lock().unwrap());
+        }
+
+        Self {
+            traffic,
+            packet_limit_hits: PACKET_LIMIT_HITS.load(Ordering::Relaxed),
+            byte_limit_hits: BYTE_LIMIT_HITS.load(Ordering::Relaxed),
+        }
+    }
+}
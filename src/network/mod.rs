@@ -1,4 +1,20 @@
+pub(crate) mod access;
+pub(crate) mod capture;
+pub(crate) mod compression;
 pub(crate) mod connection;
+pub(crate) mod encryption;
+pub(crate) mod forwarding;
+pub(crate) mod framing;
+pub(crate) mod keep_alive;
+pub(crate) mod listener;
+pub(crate) mod login_plugin;
+pub(crate) mod metrics;
 pub(crate) mod network_manager;
+pub(crate) mod proxy;
+pub(crate) mod rate_limit;
+pub(crate) mod status;
+pub(crate) mod throttle;
+pub(crate) mod timeout;
+pub(crate) mod writer;
 
 pub(crate) use network_manager::NetworkManager;
@@ -1,17 +1,22 @@
-use futures::StreamExt;
-use log::{error, trace};
+use futures::{future::BoxFuture, StreamExt};
+use log::{error, trace, warn};
 use slotmap::{DefaultKey, DenseSlotMap, SlotMap};
-use tokio::sync::mpsc::Sender;
+use tokio::sync::{broadcast::error::RecvError, mpsc::Sender};
 
-use crate::config::CONFIG;
+use crate::config::{ForwardingMode, CONFIG};
 
 use super::connection::*;
+use super::status::OnlinePlayers;
+use super::throttle::ConnectionThrottle;
+use super::{access, listener, proxy, status};
 use std::sync::Arc;
 use tokio::sync::{Mutex, RwLock};
 
-use tokio::net::TcpListener;
 use tokio::task::JoinHandle;
 
+// Accepted sockets waiting on the listener task, same as the listeners' backlog
+const ACCEPT_QUEUE: usize = 128;
+
 pub struct NetworkManager {
     connected: Option<Sender<bool>>,
     listener_thread: Option<JoinHandle<()>>,
@@ -19,6 +24,8 @@ pub struct NetworkManager {
     // TODO: Invesigate Lock-free alternatives
     // Locks when a new connection is added/removed
     pub connections: Arc<RwLock<DenseSlotMap<DefaultKey, Mutex<ServerConnection>>>>,
+    // Shown in the server list
+    pub online: OnlinePlayers,
 }
 
 impl NetworkManager {
@@ -28,6 +35,7 @@ impl NetworkManager {
             listener_thread: None,
 
             connections: Arc::new(RwLock::new(DenseSlotMap::with_key())),
+            online: OnlinePlayers::default(),
         }
     }
 
@@ -41,61 +49,75 @@ impl NetworkManager {
     }
 
     pub async fn start(&mut self) {
+        // Generate the keypair up-front rather than on the first login
+        if CONFIG.server.online_mode {
+            lazy_static::initialize(&super::encryption::SERVER_KEY);
+        }
+        status::load();
+        access::load();
+        if CONFIG.network.forwarding.mode == ForwardingMode::Velocity
+            && CONFIG.network.forwarding.secret.is_empty()
+        {
+            error!(
+                "Velocity forwarding is enabled without a secret, no player will be able to join"
+            );
+        }
+
         let (ctx, mut crx) = tokio::sync::mpsc::channel(1);
         self.connected = Some(ctx);
 
         let server_connections = self.connections.clone();
+        let online = self.online.clone();
 
         self.listener_thread = Some(tokio::task::spawn(async move {
-            let listener = TcpListener::bind(format!("127.0.0.1:{}", CONFIG.network.port))
-                .await
-                .unwrap();
+            let (accepted, mut incoming) = tokio::sync::mpsc::channel(ACCEPT_QUEUE);
+            let listeners = listener::start(accepted);
 
             let mut connections = SlotMap::with_capacity(CONFIG.network.max_players);
 
-            let mut df = futures::stream::FuturesUnordered::new();
-            let mut cf = futures::stream::FuturesUnordered::new();
+            // Polled here rather than spawned, so every connection is always removed once it closes
+            let mut df = futures::stream::FuturesUnordered::<
+                BoxFuture<'static, (DefaultKey, String)>,
+            >::new();
+            let mut cf = futures::stream::FuturesUnordered::<
+                BoxFuture<'static, (DefaultKey, Option<ServerConnection>)>,
+            >::new();
+
+            let mut throttle = ConnectionThrottle::new();
+
+            // Connections that are still sending their PROXY header
+            let mut pf = futures::stream::FuturesUnordered::new();
 
             // Handle all incoming connections
             loop {
-                tokio::select! {
+                let accepted = tokio::select! {
                     _ = crx.recv() => {
                         break;
                     }
-                    incoming = listener.accept() => {
+                    Some(incoming) = incoming.recv() => {
                         match incoming {
-                            Ok((socket, addr)) => {
-                                trace!("New connection from {}", addr);
-
-                                // Configure TCP Stream
-                                socket.set_nodelay(true).unwrap();
-
-                                let (connection, mut srv_con, mut disconnect_future) = Connection::new(socket, server_connections.read().await.len()).await;
-
-                                let key = connections.insert(connection);
-
-                                // Disconnect listening
-                                df.push(tokio::spawn(async move {
-                                    let reason = disconnect_future.recv().await.expect("Go yell at GLS or make a PR if you see this. Error: DF_LAG");
-                                    (key, reason)
-                                }));
-                                cf.push(tokio::spawn(async move {
-                                    let opt = srv_con.incoming.recv().await;
-                                    if opt.is_some() {
-                                        Some(srv_con)
-                                    } else {
-                                        None
+                            (mut socket, peer) if CONFIG.network.proxy_protocol => {
+                                // Each one waits up to HEADER_TIMEOUT, so they count towards max_pending too
+                                if let Err(e) = throttle.check_pending(pf.len()) {
+                                    warn!("Rejected connection from {}: {}", peer, e);
+                                    continue;
+                                }
+                                pf.push(tokio::spawn(async move {
+                                    match proxy::read_header(&mut socket, peer).await {
+                                        Ok(addr) => Some((socket, addr)),
+                                        Err(e) => {
+                                            warn!("Dropping connection from {}: {}", peer, e);
+                                            None
+                                        }
                                     }
                                 }));
-
-                                trace!("Connection Accepted. Total: {}", connections.len());
-                            }
-                            Err(e) => {
-                                error!("Error accepting connection: {}", e);
+                                None
                             }
+                            accepted => Some(accepted),
                         }
                     }
-                    Some(Ok((key, reason))) = df.next() => {
+                    Some(Ok(accepted)) = pf.next() => accepted,
+                    Some((key, reason)) = df.next() => {
                         /*
                             OK so we've got a disconnect request from the connection.
                             We'd naturally remove the connection from the array, but what about server_connections?
@@ -104,22 +126,66 @@ impl NetworkManager {
                             So the server will itself remove the connection from the server_connections array.
                         */
                         connections.remove(key);
+                        throttle.closed(key);
                         if reason.is_empty() {
                             trace!("Connection Closed. Total: {}", connections.len());
                         } else {
                             trace!("Connection Closed. Reason: {}. Total: {}", reason, connections.len());
                         }
+                        None
                     }
-                    Some(Ok(connection)) = cf.next() => {
+                    Some((key, connection)) = cf.next() => {
                         if let Some(connection) = connection {
+                            throttle.logged_in(key);
+
                             let mut server_connections = server_connections.write().await;
                             server_connections.insert(Mutex::new(connection));
                             trace!("Connection Registered. Total: {}", server_connections.len());
                         }
+                        None
+                    }
+                };
+
+                if let Some((socket, addr)) = accepted {
+                    trace!("New connection from {}", addr);
+
+                    if let Err(e) = throttle.check(addr.ip()) {
+                        warn!("Rejected connection from {}: {}", addr, e);
+                        continue;
                     }
+
+                    let (connection, srv_con, mut disconnect_future, logged_in) =
+                        Connection::new(socket, addr, online.clone()).await;
+
+                    let key = connections.insert(connection);
+                    throttle.opened(key, addr.ip());
+
+                    // Disconnect listening
+                    df.push(Box::pin(async move {
+                        // Missing some of the close messages still means the connection is closing
+                        let reason = match disconnect_future.recv().await {
+                            Ok(reason) => reason,
+                            Err(RecvError::Lagged(_)) | Err(RecvError::Closed) => String::new(),
+                        };
+                        (key, reason)
+                    }));
+                    cf.push(Box::pin(async move {
+                        // Dropped without firing if the client disconnects before logging in
+                        match logged_in.await {
+                            Ok(()) => (key, Some(srv_con)),
+                            Err(_) => (key, None),
+                        }
+                    }));
+
+                    trace!("Connection Accepted. Total: {}", connections.len());
                 }
             }
 
+            for listener in listeners {
+                listener.abort();
+            }
+            listener::stop();
+
             // Close all connections
             while let Some((_, connection)) = connections.drain().next() {
                 connection.destroy().await;
@@ -0,0 +1,255 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+// How long a proxy gets to send the header before the connection is dropped
+pub const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+// Longest possible v1 header, including the trailing CRLF
+const V1_MAX_LENGTH: usize = 107;
+
+#[derive(Debug)]
+pub enum ProxyError {
+    Io(std::io::Error),
+    InvalidHeader,
+    TimedOut,
+}
+
+impl std::fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProxyError::Io(e) => write!(f, "Error reading PROXY header: {}", e),
+            ProxyError::InvalidHeader => write!(f, "Invalid PROXY header"),
+            ProxyError::TimedOut => write!(f, "Timed out waiting for PROXY header"),
+        }
+    }
+}
+
+impl From<std::io::Error> for ProxyError {
+    fn from(e: std::io::Error) -> Self {
+        ProxyError::Io(e)
+    }
+}
+
+/// Consumes a PROXY protocol v1 or v2 header from the start of `socket`, returning the client's address.
+/// Falls back to `peer` for headers that don't carry an address(e.g. load balancer health checks).
+pub async fn read_header(
+    socket: &mut (impl AsyncRead + Unpin),
+    peer: SocketAddr,
+) -> Result<SocketAddr, ProxyError> {
+    match tokio::time::timeout(HEADER_TIMEOUT, read_any(socket, peer)).await {
+        // Proxies listening on both IPv4 and IPv6 can send IPv4 clients as IPv4-mapped addresses too
+        Ok(result) => result.map(|addr| SocketAddr::new(addr.ip().to_canonical(), addr.port())),
+        Err(_) => Err(ProxyError::TimedOut),
+    }
+}
+
+async fn read_any(
+    socket: &mut (impl AsyncRead + Unpin),
+    peer: SocketAddr,
+) -> Result<SocketAddr, ProxyError> {
+    // Both versions are at least this long, so this never reads into the Minecraft stream
+    let mut start = [0; 12];
+    socket.read_exact(&mut start).await?;
+
+    if &start == V2_SIGNATURE {
+        read_v2(socket, peer).await
+    } else if start.starts_with(b"PROXY ") {
+        read_v1(socket, peer, &start).await
+    } else {
+        Err(ProxyError::InvalidHeader)
+    }
+}
+
+// "PROXY TCP4 <src> <dst> <src port> <dst port>\r\n"
+async fn read_v1(
+    socket: &mut (impl AsyncRead + Unpin),
+    peer: SocketAddr,
+    start: &[u8],
+) -> Result<SocketAddr, ProxyError> {
+    let mut header = start.to_vec();
+    // Read byte by byte, anything after the header belongs to the client
+    while !header.ends_with(b"\r\n") {
+        if header.len() >= V1_MAX_LENGTH {
+            return Err(ProxyError::InvalidHeader);
+        }
+        header.push(socket.read_u8().await?);
+    }
+
+    let header =
+        std::str::from_utf8(&header[..header.len() - 2]).map_err(|_| ProxyError::InvalidHeader)?;
+    let fields = header.split(' ').collect::<Vec<_>>();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(peer),
+        ["PROXY", protocol @ ("TCP4" | "TCP6"), source, _, port, _] => {
+            let ip = source
+                .parse::<IpAddr>()
+                .map_err(|_| ProxyError::InvalidHeader)?;
+            if ip.is_ipv4() != (*protocol == "TCP4") {
+                return Err(ProxyError::InvalidHeader);
+            }
+            let port = port.parse().map_err(|_| ProxyError::InvalidHeader)?;
+            Ok(SocketAddr::new(ip, port))
+        }
+        _ => Err(ProxyError::InvalidHeader),
+    }
+}
+
+// Signature, version/command, family/protocol, u16 BE length, then `length` bytes of addresses
+async fn read_v2(
+    socket: &mut (impl AsyncRead + Unpin),
+    peer: SocketAddr,
+) -> Result<SocketAddr, ProxyError> {
+    let version_command = socket.read_u8().await?;
+    let family = socket.read_u8().await?;
+    let length = socket.read_u16().await? as usize;
+
+    let mut addresses = vec![0; length];
+    socket.read_exact(&mut addresses).await?;
+
+    if version_command >> 4 != 2 {
+        return Err(ProxyError::InvalidHeader);
+    }
+    match version_command & 0x0F {
+        // LOCAL, sent by the proxy itself
+        0x0 => return Ok(peer),
+        // PROXY
+        0x1 => {}
+        _ => return Err(ProxyError::InvalidHeader),
+    }
+
+    match family >> 4 {
+        // AF_INET
+        0x1 => {
+            if length < 12 {
+                return Err(ProxyError::InvalidHeader);
+            }
+            let ip: [u8; 4] = addresses[0..4].try_into().unwrap();
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            Ok(SocketAddr::new(Ipv4Addr::from(ip).into(), port))
+        }
+        // AF_INET6
+        0x2 => {
+            if length < 36 {
+                return Err(ProxyError::InvalidHeader);
+            }
+            let ip: [u8; 16] = addresses[0..16].try_into().unwrap();
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Ok(SocketAddr::new(Ipv6Addr::from(ip).into(), port))
+        }
+        // AF_UNSPEC or AF_UNIX, neither of which has a usable address
+        _ => Ok(peer),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEER: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 40000);
+
+    // Reads the header from `data`, returning the address and whatever was left unread
+    async fn read(data: &[u8]) -> Result<(SocketAddr, Vec<u8>), ProxyError> {
+        let mut reader = data;
+        let address = read_header(&mut reader, PEER).await?;
+        Ok((address, reader.to_vec()))
+    }
+
+    fn v2(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.push(0x20 | command);
+        header.push(family);
+        header.extend((addresses.len() as u16).to_be_bytes());
+        header.extend(addresses);
+        header
+    }
+
+    #[tokio::test]
+    async fn v1_tcp4() {
+        let (address, rest) = read(b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 25565\r\n\x10\x00")
+            .await
+            .unwrap();
+        assert_eq!(address, "192.168.0.1:56324".parse().unwrap());
+        assert_eq!(rest, b"\x10\x00");
+    }
+
+    #[tokio::test]
+    async fn v1_tcp6() {
+        let (address, _) = read(b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 25565\r\n")
+            .await
+            .unwrap();
+        assert_eq!(address, "[2001:db8::1]:56324".parse().unwrap());
+    }
+
+    #[tokio::test]
+    async fn v1_tcp6_mapped() {
+        let (address, _) =
+            read(b"PROXY TCP6 ::ffff:192.168.0.1 ::ffff:192.168.0.11 56324 25565\r\n")
+                .await
+                .unwrap();
+        assert_eq!(address, "192.168.0.1:56324".parse().unwrap());
+    }
+
+    #[tokio::test]
+    async fn v1_unknown() {
+        let (address, rest) = read(b"PROXY UNKNOWN\r\n\x10").await.unwrap();
+        assert_eq!(address, PEER);
+        assert_eq!(rest, b"\x10");
+    }
+
+    #[tokio::test]
+    async fn v1_too_long() {
+        let mut header = b"PROXY TCP4 ".to_vec();
+        header.extend([b'1'; V1_MAX_LENGTH]);
+        header.extend(b"\r\n");
+        assert!(matches!(
+            read(&header).await,
+            Err(ProxyError::InvalidHeader)
+        ));
+    }
+
+    #[tokio::test]
+    async fn v2_tcp4() {
+        let mut data = v2(
+            0x1,
+            0x11,
+            &[192, 168, 0, 1, 192, 168, 0, 11, 0xDC, 0x04, 0x63, 0xDD],
+        );
+        data.push(0x10);
+        let (address, rest) = read(&data).await.unwrap();
+        assert_eq!(address, "192.168.0.1:56324".parse().unwrap());
+        assert_eq!(rest, b"\x10");
+    }
+
+    #[tokio::test]
+    async fn v2_tcp6() {
+        let source: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let mut addresses = source.octets().to_vec();
+        addresses.extend([0; 16]);
+        addresses.extend([0xDC, 0x04, 0x63, 0xDD]);
+        let (address, _) = read(&v2(0x1, 0x21, &addresses)).await.unwrap();
+        assert_eq!(address, "[2001:db8::1]:56324".parse().unwrap());
+    }
+
+    #[tokio::test]
+    async fn v2_local() {
+        let (address, rest) = read(&v2(0x0, 0x00, &[])).await.unwrap();
+        assert_eq!(address, PEER);
+        assert!(rest.is_empty());
+    }
+
+    #[tokio::test]
+    async fn v2_truncated() {
+        let data = v2(
+            0x1,
+            0x11,
+            &[192, 168, 0, 1, 192, 168, 0, 11, 0xDC, 0x04, 0x63, 0xDD],
+        );
+        assert!(matches!(
+            read(&data[..data.len() - 4]).await,
+            Err(ProxyError::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof
+        ));
+    }
+}
@@ -0,0 +1,113 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use crate::{
+    config::{RateLimit, CONFIG},
+    packets::types::ConnectionState,
+};
+
+// Times a client was kicked for going over a limit, across all connections
+pub static PACKET_LIMIT_HITS: AtomicU64 = AtomicU64::new(0);
+pub static BYTE_LIMIT_HITS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug)]
+pub enum RateLimitError {
+    TooManyPackets,
+    TooManyBytes,
+}
+
+impl std::fmt::Display for RateLimitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RateLimitError::TooManyPackets => write!(f, "Sending packets too fast"),
+            RateLimitError::TooManyBytes => write!(f, "Sending data too fast"),
+        }
+    }
+}
+
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit) -> Option<Self> {
+        if limit.rate == 0 {
+            return None;
+        }
+
+        // The bucket must at least hold a second's worth of tokens
+        let capacity = limit.burst.max(limit.rate) as f64;
+        Some(Self {
+            rate: limit.rate as f64,
+            capacity,
+            tokens: capacity,
+            last: Instant::now(),
+        })
+    }
+
+    fn take(&mut self, amount: f64) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.last = now;
+
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        if self.tokens < amount {
+            return false;
+        }
+        self.tokens -= amount;
+        true
+    }
+}
+
+/// Per-connection limits on what the client sends. Owned by the reader task.
+pub struct RateLimiter {
+    bytes: Option<TokenBucket>,
+    handshake: Option<TokenBucket>,
+    status: Option<TokenBucket>,
+    login: Option<TokenBucket>,
+    play: Option<TokenBucket>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        let config = &CONFIG.network.rate_limit;
+        Self {
+            bytes: TokenBucket::new(config.bytes),
+            handshake: TokenBucket::new(config.serverbound.handshake),
+            status: TokenBucket::new(config.serverbound.status),
+            login: TokenBucket::new(config.serverbound.login),
+            play: TokenBucket::new(config.serverbound.play),
+        }
+    }
+
+    /// Called for every read from the socket
+    pub fn bytes(&mut self, read: usize) -> Result<(), RateLimitError> {
+        if let Some(bucket) = &mut self.bytes {
+            if !bucket.take(read as f64) {
+                BYTE_LIMIT_HITS.fetch_add(1, Ordering::Relaxed);
+                return Err(RateLimitError::TooManyBytes);
+            }
+        }
+        Ok(())
+    }
+
+    /// Called for every packet received in `state`
+    pub fn packet(&mut self, state: &ConnectionState) -> Result<(), RateLimitError> {
+        let bucket = match state {
+            ConnectionState::Handshake => &mut self.handshake,
+            ConnectionState::Status => &mut self.status,
+            ConnectionState::Login => &mut self.login,
+            ConnectionState::Play => &mut self.play,
+        };
+        if let Some(bucket) = bucket {
+            if !bucket.take(1.0) {
+                PACKET_LIMIT_HITS.fetch_add(1, Ordering::Relaxed);
+                return Err(RateLimitError::TooManyPackets);
+            }
+        }
+        Ok(())
+    }
+}
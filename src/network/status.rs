@@ -0,0 +1,343 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use base64::Engine;
+use lazy_static::lazy_static;
+use log::{info, warn};
+use rand::seq::SliceRandom;
+use tokio::sync::{RwLock, RwLockReadGuard};
+
+use crate::{
+    config::CONFIG,
+    packets::{types::Uuid, version::ProtocolVersion},
+};
+
+// Loaded from the working directory, same as vanilla
+const FAVICON_PATH: &str = "server-icon.png";
+const FAVICON_SIZE: u32 = 64;
+const PNG_SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+
+// Longest Status Response clients accept, in characters
+const MAX_RESPONSE_LENGTH: usize = 32767;
+// Left for everything but the MOTD and favicon: the version and the player count and sample
+const RESERVED_LENGTH: usize = 4096;
+
+lazy_static! {
+    static ref FAVICON: Option<String> = load_favicon();
+    static ref DESCRIPTION: serde_json::Value = load_description();
+}
+
+/// Players currently on the server, shared between the server and every connection
+#[derive(Clone, Default)]
+pub struct OnlinePlayers {
+    // Kept up to date by the server, for the server list
+    players: Arc<RwLock<Vec<Player>>>,
+    // Taken as soon as a login finishes, rather than once the server has added the player,
+    // so logins finishing at the same time can't all fit in the last slot
+    slots: Arc<AtomicUsize>,
+}
+
+impl OnlinePlayers {
+    pub async fn players(&self) -> RwLockReadGuard<'_, Vec<Player>> {
+        self.players.read().await
+    }
+
+    pub async fn set_players(&self, players: Vec<Player>) {
+        *self.players.write().await = players;
+    }
+
+    /// Takes a slot if fewer than `limit` are taken. Always succeeds without a limit
+    pub fn take_slot(&self, limit: Option<usize>) -> bool {
+        self.slots
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |taken| match limit {
+                Some(limit) if taken >= limit => None,
+                _ => Some(taken + 1),
+            })
+            .is_ok()
+    }
+
+    /// Gives back the slot of a player that has left
+    pub fn free_slot(&self) {
+        let _ = self
+            .slots
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |taken| {
+                taken.checked_sub(1)
+            });
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct StatusResponse {
+    version: Version,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    players: Option<Players>,
+    description: serde_json::Value,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    favicon: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct Version {
+    name: String,
+    protocol: i32,
+}
+
+#[derive(serde::Serialize)]
+struct Players {
+    max: usize,
+    online: usize,
+    sample: Vec<Player>,
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct Player {
+    name: String,
+    id: String,
+}
+
+impl Player {
+    pub fn new(name: &str, uuid: Uuid) -> Self {
+        Self {
+            name: name.to_string(),
+            id: uuid.to_string(),
+        }
+    }
+}
+
+pub enum FaviconError {
+    NotPng,
+    WrongSize(u32, u32),
+    TooLarge(usize, usize),
+}
+
+impl std::fmt::Display for FaviconError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FaviconError::NotPng => write!(f, "Not a PNG image"),
+            FaviconError::WrongSize(width, height) => write!(
+                f,
+                "Image is {}x{}, it must be {}x{}",
+                width, height, FAVICON_SIZE, FAVICON_SIZE
+            ),
+            FaviconError::TooLarge(length, limit) => write!(
+                f,
+                "Image is {} characters once encoded, only {} fit in the server list",
+                length, limit
+            ),
+        }
+    }
+}
+
+/// Loads the favicon and MOTD up-front, so problems with them are logged at startup
+pub fn load() {
+    lazy_static::initialize(&FAVICON);
+    lazy_static::initialize(&DESCRIPTION);
+}
+
+fn load_favicon() -> Option<String> {
+    let image = match std::fs::read(FAVICON_PATH) {
+        Ok(image) => image,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            warn!("Failed to load {}: {}", FAVICON_PATH, e);
+            return None;
+        }
+    };
+
+    // Whatever the MOTD leaves free
+    let limit = MAX_RESPONSE_LENGTH - RESERVED_LENGTH - DESCRIPTION.to_string().chars().count();
+    match encode_favicon(&image, limit) {
+        Ok(favicon) => {
+            info!("Loaded {}", FAVICON_PATH);
+            Some(favicon)
+        }
+        Err(e) => {
+            warn!("Failed to load {}: {}", FAVICON_PATH, e);
+            None
+        }
+    }
+}
+
+fn encode_favicon(image: &[u8], limit: usize) -> Result<String, FaviconError> {
+    // The signature is followed by the IHDR chunk, which always comes first: length, type, width, height
+    if image.len() < 24 || &image[..8] != PNG_SIGNATURE || &image[12..16] != b"IHDR" {
+        return Err(FaviconError::NotPng);
+    }
+    let width = u32::from_be_bytes(image[16..20].try_into().unwrap());
+    let height = u32::from_be_bytes(image[20..24].try_into().unwrap());
+    if width != FAVICON_SIZE || height != FAVICON_SIZE {
+        return Err(FaviconError::WrongSize(width, height));
+    }
+
+    let favicon = format!(
+        "data:image/png;base64,{}",
+        base64::engine::general_purpose::STANDARD.encode(image)
+    );
+    if favicon.len() > limit {
+        return Err(FaviconError::TooLarge(favicon.len(), limit));
+    }
+    Ok(favicon)
+}
+
+fn load_description() -> serde_json::Value {
+    let fits = |description: &serde_json::Value| {
+        description.to_string().chars().count() <= MAX_RESPONSE_LENGTH - RESERVED_LENGTH
+    };
+
+    let motd_json = &CONFIG.server.motd_json;
+    if !motd_json.is_empty() {
+        match serde_json::from_str(motd_json) {
+            Ok(description) if fits(&description) => return description,
+            Ok(_) => warn!("motd_json is too long, using motd instead"),
+            Err(e) => warn!("Invalid motd_json, using motd instead: {}", e),
+        }
+    }
+
+    let description = serde_json::json!({ "text": CONFIG.server.motd });
+    if !fits(&description) {
+        warn!("motd is too long, the server list won't show it");
+        return serde_json::json!({ "text": "" });
+    }
+    description
+}
+
+impl StatusResponse {
+    pub fn new(online: &[Player], version: &ProtocolVersion) -> Self {
+        let config = &CONFIG.server;
+
+        // Vanilla also picks a random few, so everyone gets a turn on busy servers
+        let sample = online
+            .choose_multiple(&mut rand::thread_rng(), config.player_sample)
+            .cloned()
+            .collect();
+
+        Self {
+            version: Version {
+                name: match config.version_name.is_empty() {
+                    true => version.name.to_string(),
+                    false => config.version_name.clone(),
+                },
+                protocol: version.protocol as i32,
+            },
+            // Clients show "???" instead of the count
+            players: match config.hide_player_count {
+                true => None,
+                false => Some(Players {
+                    max: CONFIG.network.max_players,
+                    online: online.len(),
+                    sample,
+                }),
+            },
+            description: DESCRIPTION.clone(),
+            favicon: FAVICON.clone(),
+        }
+    }
+
+    /// Never longer than clients accept. The favicon and sample are left out if they don't fit,
+    /// then the configured version name
+    pub fn into_json(mut self) -> String {
+        let json = serde_json::to_string(&self).unwrap();
+        if json.chars().count() <= MAX_RESPONSE_LENGTH {
+            return json;
+        }
+        warn!("Status response is too long, leaving out the favicon and player sample");
+        self.favicon = None;
+        if let Some(players) = &mut self.players {
+            players.sample.clear();
+        }
+
+        let json = serde_json::to_string(&self).unwrap();
+        if json.chars().count() <= MAX_RESPONSE_LENGTH {
+            return json;
+        }
+        warn!("version_name is too long, sending the client's version instead");
+        self.version.name = ProtocolVersion::lookup(self.version.protocol as u32)
+            .unwrap_or(ProtocolVersion::latest())
+            .name
+            .to_string();
+        serde_json::to_string(&self).unwrap()
+    }
+
+    /// The response to a pre-1.7 server list ping.
+    /// A kick packet(0xFF) carrying a UTF-16BE string, prefixed by its length in code units.
+    pub fn to_legacy(&self) -> Vec<u8> {
+        // Old clients can't show a hidden count, or formatting beyond the plain MOTD
+        let (online, max) = match &self.players {
+            Some(players) => (players.online, players.max),
+            None => (0, 0),
+        };
+        let response = format!(
+            "§1\0{}\0{}\0{}\0{}\0{}",
+            self.version.protocol, self.version.name, CONFIG.server.motd, online, max
+        );
+        let response = response.encode_utf16().collect::<Vec<u16>>();
+
+        let mut data = Vec::with_capacity(3 + response.len() * 2);
+        data.push(0xFF);
+        data.extend((response.len() as u16).to_be_bytes());
+        for unit in response {
+            data.extend(unit.to_be_bytes());
+        }
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Just the parts of a PNG that are checked: the signature and the start of the IHDR chunk
+    fn png(width: u32, height: u32, padding: usize) -> Vec<u8> {
+        let mut image = PNG_SIGNATURE.to_vec();
+        image.extend(13u32.to_be_bytes());
+        image.extend(b"IHDR");
+        image.extend(width.to_be_bytes());
+        image.extend(height.to_be_bytes());
+        image.extend(vec![0; padding]);
+        image
+    }
+
+    #[test]
+    fn favicon() {
+        let favicon = encode_favicon(&png(64, 64, 0), MAX_RESPONSE_LENGTH)
+            .ok()
+            .unwrap();
+        assert_eq!(
+            favicon,
+            "data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAEAAAABA"
+        );
+    }
+
+    #[test]
+    fn favicon_not_png() {
+        assert!(matches!(
+            encode_favicon(b"GIF89a", MAX_RESPONSE_LENGTH),
+            Err(FaviconError::NotPng)
+        ));
+        assert!(matches!(
+            encode_favicon(&png(64, 64, 0)[..20], MAX_RESPONSE_LENGTH),
+            Err(FaviconError::NotPng)
+        ));
+    }
+
+    #[test]
+    fn favicon_wrong_size() {
+        assert!(matches!(
+            encode_favicon(&png(128, 64, 0), MAX_RESPONSE_LENGTH),
+            Err(FaviconError::WrongSize(128, 64))
+        ));
+    }
+
+    #[test]
+    fn favicon_too_large() {
+        // Metadata chunks can make a 64x64 image arbitrarily large
+        let image = png(64, 64, MAX_RESPONSE_LENGTH);
+        assert!(matches!(
+            encode_favicon(&image, MAX_RESPONSE_LENGTH),
+            Err(FaviconError::TooLarge(_, MAX_RESPONSE_LENGTH))
+        ));
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use slotmap::DefaultKey;
+
+use crate::config::CONFIG;
+
+// Forget IPs that haven't connected within the window once this many are being tracked
+const CLEANUP_THRESHOLD: usize = 1024;
+
+#[derive(Debug)]
+pub enum ThrottleError {
+    // Too many connections within the throttle window
+    Throttled,
+    TooManyConnections(usize),
+    TooManyPending(usize),
+}
+
+impl std::fmt::Display for ThrottleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ThrottleError::Throttled => write!(f, "Connection throttled"),
+            ThrottleError::TooManyConnections(open) => {
+                write!(f, "Too many connections from this address ({})", open)
+            }
+            ThrottleError::TooManyPending(pending) => {
+                write!(f, "Too many connections still logging in ({})", pending)
+            }
+        }
+    }
+}
+
+struct OpenConnection {
+    ip: IpAddr,
+    // Still in Handshake/Status/Login
+    pending: bool,
+}
+
+/// Limits how often and how many connections each IP can open. Owned by the listener task.
+/// Loopback addresses only count towards `max_pending`, as local proxies connect from them on behalf of every player.
+pub struct ConnectionThrottle {
+    recent: HashMap<IpAddr, VecDeque<Instant>>,
+    open_per_ip: HashMap<IpAddr, usize>,
+    connections: HashMap<DefaultKey, OpenConnection>,
+    pending: usize,
+}
+
+impl ConnectionThrottle {
+    pub fn new() -> Self {
+        Self {
+            recent: HashMap::new(),
+            open_per_ip: HashMap::new(),
+            connections: HashMap::new(),
+            pending: 0,
+        }
+    }
+
+    /// Checks whether `ip` may open another connection, counting it towards the throttle if so
+    pub fn check(&mut self, ip: IpAddr) -> Result<(), ThrottleError> {
+        let config = &CONFIG.network.throttle;
+
+        self.check_pending(0)?;
+        if ip.is_loopback() {
+            return Ok(());
+        }
+
+        let open = self.open_per_ip.get(&ip).copied().unwrap_or(0);
+        if config.max_per_ip > 0 && open >= config.max_per_ip {
+            return Err(ThrottleError::TooManyConnections(open));
+        }
+
+        if config.connections_per_window > 0 {
+            let now = Instant::now();
+            let window = Duration::from_millis(config.window_ms);
+
+            if self.recent.len() > CLEANUP_THRESHOLD {
+                self.recent.retain(|_, recent| {
+                    recent
+                        .back()
+                        .is_some_and(|last| now.duration_since(*last) < window)
+                });
+            }
+
+            let recent = self.recent.entry(ip).or_default();
+            while let Some(oldest) = recent.front() {
+                if now.duration_since(*oldest) < window {
+                    break;
+                }
+                recent.pop_front();
+            }
+
+            if recent.len() >= config.connections_per_window {
+                return Err(ThrottleError::Throttled);
+            }
+            recent.push_back(now);
+        }
+
+        Ok(())
+    }
+
+    /// Checks `max_pending` alone, also counting `waiting` connections that aren't tracked yet
+    pub fn check_pending(&self, waiting: usize) -> Result<(), ThrottleError> {
+        let max_pending = CONFIG.network.throttle.max_pending;
+        let pending = self.pending + waiting;
+        if max_pending > 0 && pending >= max_pending {
+            return Err(ThrottleError::TooManyPending(pending));
+        }
+        Ok(())
+    }
+
+    pub fn opened(&mut self, key: DefaultKey, ip: IpAddr) {
+        *self.open_per_ip.entry(ip).or_default() += 1;
+        self.connections
+            .insert(key, OpenConnection { ip, pending: true });
+        self.pending += 1;
+    }
+
+    /// The connection finished logging in and was handed to the server
+    pub fn logged_in(&mut self, key: DefaultKey) {
+        if let Some(connection) = self.connections.get_mut(&key) {
+            if connection.pending {
+                connection.pending = false;
+                self.pending -= 1;
+            }
+        }
+    }
+
+    pub fn closed(&mut self, key: DefaultKey) {
+        let connection = match self.connections.remove(&key) {
+            Some(connection) => connection,
+            None => return,
+        };
+
+        if connection.pending {
+            self.pending -= 1;
+        }
+        if let Some(open) = self.open_per_ip.get_mut(&connection.ip) {
+            *open -= 1;
+            if *open == 0 {
+                self.open_per_ip.remove(&connection.ip);
+            }
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::{config::CONFIG, packets::types::ConnectionState};
+
+#[derive(Debug, Clone, Copy)]
+pub enum TimeoutError {
+    Handshake,
+    Login,
+    Frame,
+}
+
+impl std::fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TimeoutError::Handshake => write!(f, "Timed out waiting for handshake"),
+            TimeoutError::Login => write!(f, "Timed out waiting for login to finish"),
+            TimeoutError::Frame => write!(f, "Timed out waiting for the rest of a packet"),
+        }
+    }
+}
+
+/// Deadlines a client has to meet before it's disconnected. Owned by the reader task.
+pub struct Deadlines {
+    connected: Instant,
+    // When the client started sending the frame that's still incomplete
+    partial_since: Option<Instant>,
+}
+
+impl Deadlines {
+    pub fn new() -> Self {
+        Self {
+            connected: Instant::now(),
+            partial_since: None,
+        }
+    }
+
+    /// Called after the frames in a read have been handled.
+    /// The frame deadline only restarts once a frame completes, so trickling in a byte at a time doesn't extend it.
+    pub fn frames_read(&mut self, completed: bool, partial: bool) {
+        if completed || !partial {
+            self.partial_since = None;
+        }
+        if partial && self.partial_since.is_none() {
+            self.partial_since = Some(Instant::now());
+        }
+    }
+
+    /// The earliest deadline that applies in `state`
+    pub fn next(&self, state: &ConnectionState) -> Option<(Instant, TimeoutError)> {
+        let config = &CONFIG.network.timeouts;
+
+        let stage = match state {
+            ConnectionState::Handshake => deadline(self.connected, config.handshake_ms)
+                .map(|at| (at, TimeoutError::Handshake)),
+            ConnectionState::Status | ConnectionState::Login => {
+                deadline(self.connected, config.login_ms).map(|at| (at, TimeoutError::Login))
+            }
+            ConnectionState::Play => None,
+        };
+        let frame = self
+            .partial_since
+            .and_then(|since| deadline(since, config.frame_ms))
+            .map(|at| (at, TimeoutError::Frame));
+
+        match (stage, frame) {
+            (Some(stage), Some(frame)) => Some(if frame.0 < stage.0 { frame } else { stage }),
+            (stage, frame) => stage.or(frame),
+        }
+    }
+}
+
+fn deadline(from: Instant, ms: u64) -> Option<Instant> {
+    (ms > 0).then(|| from + Duration::from_millis(ms))
+}
+
+/// Resolves once `deadline` has passed, never if there isn't one
+pub async fn expired(deadline: Option<(Instant, TimeoutError)>) -> TimeoutError {
+    match deadline {
+        Some((at, error)) => {
+            tokio::time::sleep_until(at).await;
+            error
+        }
+        None => std::future::pending().await,
+    }
+}
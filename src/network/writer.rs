@@ -0,0 +1,208 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use log::{debug, error, trace};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::{broadcast, RwLock};
+
+use crate::{
+    config::CONFIG,
+    packets::{serial, types::v32, Packets},
+};
+
+use super::capture::{Capture, Direction};
+use super::compression::{self, Deflater};
+use super::encryption::Encryptor;
+use super::framing::MAX_FRAME_LENGTH;
+use super::metrics::ConnectionMetrics;
+
+/// Encodes outgoing packets into a single reusable buffer, which is written to the socket on `flush`.
+pub(super) struct PacketWriter<W> {
+    socket: W,
+    close_sender: broadcast::Sender<String>,
+
+    compressed: Arc<RwLock<bool>>,
+    protocol: Arc<RwLock<u32>>,
+    capture: Arc<Capture>,
+    metrics: Arc<ConnectionMetrics>,
+    encryptor: Option<Encryptor>,
+    // Created when the first packet is compressed
+    deflater: Option<Deflater>,
+
+    buffer: Vec<u8>,
+}
+
+impl<W: AsyncWrite + Unpin> PacketWriter<W> {
+    pub fn new(
+        socket: W,
+        close_sender: broadcast::Sender<String>,
+        compressed: Arc<RwLock<bool>>,
+        protocol: Arc<RwLock<u32>>,
+        capture: Arc<Capture>,
+        metrics: Arc<ConnectionMetrics>,
+    ) -> Self {
+        Self {
+            socket,
+            close_sender,
+            compressed,
+            protocol,
+            capture,
+            metrics,
+            encryptor: None,
+            deflater: None,
+            buffer: Vec::with_capacity(CONFIG.network.advanced.buffer_size),
+        }
+    }
+
+    /// Encodes `packet` onto the end of the buffer
+    pub async fn queue(&mut self, packet: Packets) {
+        trace!("Sending packet: {:?}", packet);
+        let started = Instant::now();
+        let mut bytes = Vec::new();
+
+        let protocol = *self.protocol.read().await;
+        let id = match packet.get_id(protocol) {
+            Some(id) => id,
+            None => {
+                error!("{:?} doesn't exist in protocol {}", packet, protocol);
+                return;
+            }
+        };
+
+        // Internal packets don't have a state, and never reach the client
+        let state = packet.get_state();
+
+        let mut should_enable_compression = false; // TODO: Something better
+        match packet {
+            Packets::InternalNetworkDisconnect(_) => {
+                self.close_sender.send("".to_string()).unwrap();
+            }
+            Packets::InternalNetworkFlush(_) => {
+                return;
+            }
+            Packets::ClientboundLoginDisconnect(packet) => {
+                bytes.extend(serial::encode_to_vec(packet.as_ref()).unwrap());
+
+                debug!(
+                    "Disconnecting client: {}",
+                    String::from(packet.reason.value)
+                );
+                self.close_sender.send("".to_string()).unwrap();
+            }
+            Packets::ClientboundLoginSetCompression(_) => {
+                should_enable_compression = true;
+                bytes.extend(packet.get_data());
+            }
+            Packets::InternalNetworkLegacyPingResponse(packet) => {
+                // Pre-netty clients don't understand frames, so this is written as-is
+                self.buffer.extend(&packet.data);
+                self.close_sender.send("".to_string()).unwrap();
+                return;
+            }
+            Packets::InternalNetworkEnableEncryption(packet) => {
+                // Everything queued after this point is encrypted
+                self.encryptor = Encryptor::new(&packet.shared_secret);
+                return;
+            }
+            _ => {
+                bytes.extend(packet.get_data());
+            }
+        }
+
+        // Capturing isn't counted towards the time spent encoding
+        let encoded = started.elapsed();
+        if let Some(state) = &state {
+            self.capture.record(
+                Direction::Clientbound,
+                state,
+                protocol,
+                u32::from(id),
+                &bytes,
+            );
+        }
+        let started = Instant::now();
+
+        // Normal packet
+        let len = (bytes.len() + v32::byte_size(u32::from(id))) as u32;
+        let mut data = Vec::with_capacity(len as usize + v32::byte_size(len));
+        data.extend(serial::encode_to_vec(&v32::from(len)).unwrap());
+        data.extend(serial::encode_to_vec(&id).unwrap());
+        data.extend(bytes);
+
+        if *self.compressed.read().await {
+            let skip = v32::byte_size(len);
+
+            let compressed_data =
+                if compression::threshold().is_some_and(|threshold| len as usize >= threshold) {
+                    self.deflater
+                        .get_or_insert_with(Deflater::new)
+                        .compress(&data[skip..])
+                } else {
+                    None
+                };
+
+            // Data Length is the uncompressed size, or 0 if the packet is sent as-is
+            let (data_length, body) = match compressed_data {
+                Some(compressed_data) => (len, compressed_data),
+                None => (0, &data[skip..]),
+            };
+
+            // Compressed packet
+            let len = body.len() + v32::byte_size(data_length);
+            let mut bytes = Vec::with_capacity(v32::byte_size(len as u32) + len);
+            bytes.extend(serial::encode_to_vec(&v32::from(len as u32)).unwrap());
+            bytes.extend(serial::encode_to_vec(&v32::from(data_length)).unwrap());
+            bytes.extend(body);
+
+            data = bytes;
+        }
+
+        if data.len() > MAX_FRAME_LENGTH {
+            error!("Packet too large! {}", data.len());
+            self.close_sender
+                .send(format!("Server tried sending Packet size {}", data.len()))
+                .unwrap();
+            return;
+        }
+
+        trace!("Queued {} bytes for client", data.len());
+        if let Some(state) = &state {
+            self.metrics.record(
+                Direction::Clientbound,
+                state,
+                u32::from(id),
+                len as usize,
+                data.len(),
+                encoded + started.elapsed(),
+            );
+        }
+
+        if let Some(encryptor) = &mut self.encryptor {
+            encryptor.encrypt(&mut data);
+        }
+        self.buffer.extend(data);
+
+        /*
+            As SetCompression is never recieved and packets are only compressed AFTER,
+            we *shouldn't* have to worry about syncing this with the reader.
+        */
+        if should_enable_compression {
+            *self.compressed.write().await = true;
+        }
+    }
+
+    /// Writes everything queued so far in as few writes as possible
+    pub async fn flush(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+
+        trace!("Sending {} bytes to client", self.buffer.len());
+
+        if let Err(e) = self.socket.write_all(&self.buffer).await {
+            error!("Error writing to connection: {}", e);
+        }
+
+        self.buffer.clear();
+    }
+}
@@ -0,0 +1,54 @@
+use super::{serial::DecodeError, types::v32};
+
+#[derive(Debug)]
+pub enum PacketErrorKind {
+    UnknownId,
+    // Failed while decoding `field`, `offset` bytes into the packet data
+    Decode {
+        field: &'static str,
+        offset: usize,
+        error: DecodeError,
+    },
+    // The packet decoded fine, but the client sent more data than it contains
+    TrailingBytes {
+        offset: usize,
+    },
+}
+
+/// Returned by the generated `decode_packet` functions
+#[derive(Debug)]
+pub struct PacketError {
+    pub direction: &'static str,
+    pub state: &'static str,
+    pub id: v32,
+    pub kind: PacketErrorKind,
+}
+
+impl std::fmt::Display for PacketError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} packet 0x{:02X}: ",
+            self.direction,
+            self.state,
+            u32::from(self.id)
+        )?;
+        match &self.kind {
+            PacketErrorKind::UnknownId => write!(f, "unknown packet id"),
+            PacketErrorKind::Decode {
+                field,
+                offset,
+                error,
+            } => write!(
+                f,
+                "failed to decode field '{}' at byte {}: {:?}",
+                field, offset, error
+            ),
+            PacketErrorKind::TrailingBytes { offset } => {
+                write!(f, "unexpected data after byte {}", offset)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PacketError {}
@@ -1,9 +1,15 @@
 extern crate snap_rs_proc_macros;
 
+mod error;
 pub mod serial;
 pub mod types;
+pub mod version;
 
-pub(self) use snap_rs_proc_macros::packets;
+use snap_rs_proc_macros::packets;
+
+pub use error::{PacketError, PacketErrorKind};
+
+use std::net::SocketAddr;
 
 use types::*;
 
@@ -224,14 +230,16 @@ pub enum PacketState {
     Play,
 }
 
-impl From<u8> for PacketState {
-    fn from(id: u8) -> Self {
+impl TryFrom<u8> for PacketState {
+    type Error = u8;
+
+    fn try_from(id: u8) -> Result<Self, Self::Error> {
         match id {
-            0 => Self::Handshake,
-            1 => Self::Status,
-            2 => Self::Login,
-            3 => Self::Play,
-            _ => panic!("Unknown packet state id: {}", id),
+            0 => Ok(Self::Handshake),
+            1 => Ok(Self::Status),
+            2 => Ok(Self::Login),
+            3 => Ok(Self::Play),
+            _ => Err(id),
         }
     }
 }
@@ -253,13 +261,15 @@ impl From<&PacketState> for u8 {
       be removed from the struct and will only exist during decoding/encoding.
     - Most of the parameters in a Struct is going to be automatically inferred to a public visability.
       The only situation you would manually specify a `pub` visability is if you want to ensure a length field is kept.
+    - If a packet's id changes between protocol versions, the id is replaced with a list of version ranges, e.g.
+      `[754 => 0x19, 755..=758 => 0x1A] => Disconnect { ... }`. Versions not listed don't have the packet at all.
 */
 packets! {
     Serverbound => {
         Handshaking => {
             0x00 => Handshake {
                 protocol_version: v32,
-                server_address: BoundedString<255>,
+                server_address: BoundedString<32767>, // Vanilla limits this to 255, BungeeCord forwarding doesn't
                 server_port: u16,
                 next_state: u8, // is technically a varint, but the valid range is within a u8
             },
@@ -280,6 +290,20 @@ packets! {
                 verify_token_length: v32,
                 verify_token: Vec<u8, verify_token_length>,
             },
+            0x02 => PluginResponse {
+                message_id: v32,
+                successful: bool,
+                data: Vec<u8, remain>,
+            },
+        },
+        Play => {
+            [754 => 0x0B, 755..=758 => 0x0A] => PluginMessage {
+                channel: Identifier,
+                data: Vec<u8, remain>,
+            },
+            [754 => 0x10, 755..=758 => 0x0F] => KeepAlive {
+                id: i64,
+            },
         }
     },
     Clientbound => {
@@ -303,30 +327,56 @@ packets! {
                 verify_token: Vec<u8, verify_token_length>,
             },
             0x02 => LoginSuccess {
-                uuid: BoundedString<36>,
+                uuid: Uuid,
                 username: BoundedString<16>,
             },
             0x03 => SetCompression {
                 threshold: v32,
             },
+            0x04 => PluginRequest {
+                message_id: v32,
+                channel: Identifier,
+                data: Vec<u8, remain>,
+            },
         },
         Play => {
-            0x19 => Disconnect {
+            [754 => 0x17, 755..=758 => 0x18] => PluginMessage {
+                channel: Identifier,
+                data: Vec<u8, remain>,
+            },
+            [754 => 0x19, 755..=758 => 0x1A] => Disconnect {
                 reason: Chat,
             },
+            [754 => 0x1F, 755..=758 => 0x21] => KeepAlive {
+                id: i64,
+            },
         }
     },
     Internal => {
         Server => {
             0x00 => Initalize : Ignore {
-                uuid: String,
+                uuid: Uuid,
                 username: String,
+                address: SocketAddr,
+                properties: Vec<Property>,
+            },
+            // Smoothed keep-alive round trip in milliseconds
+            0x01 => Latency : Ignore {
+                latency: u32,
             },
         },
         Network => {
             0x00 => Disconnect {
                 reason: BoundedString<32767>,
             },
+            0x01 => EnableEncryption : Ignore {
+                shared_secret: Vec<u8>,
+            },
+            0x02 => LegacyPingResponse : Ignore {
+                data: Vec<u8>,
+            },
+            // Sent by the Server at the end of a tick when `flush_policy` is "end_of_tick"
+            0x03 => Flush : Ignore {},
         }
     },
 }
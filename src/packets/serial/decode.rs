@@ -13,16 +13,39 @@ pub trait Decode {
 pub struct Decoder<'a> {
     pub(self) buffer: &'a [u8],
     pub(self) offset: usize,
+
+    // Name of the packet field currently being decoded, for error reporting
+    pub(self) field: &'static str,
 }
 
-impl Decoder<'_> {
+impl<'a> Decoder<'a> {
+    pub fn new(buffer: &'a [u8]) -> Self {
+        Self {
+            buffer,
+            offset: 0,
+            field: "",
+        }
+    }
+
     pub fn remaining(&self) -> usize {
         self.buffer.len() - self.offset
     }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn field(&self) -> &'static str {
+        self.field
+    }
+
+    pub fn set_field(&mut self, field: &'static str) {
+        self.field = field;
+    }
 }
 
 pub fn decode_from_slice<R: Decode>(buffer: &[u8]) -> Result<(R, usize), DecodeError> {
-    let mut decoder = Decoder { buffer, offset: 0 };
+    let mut decoder = Decoder::new(buffer);
     Ok((<R as Decode>::decode(&mut decoder)?, decoder.offset)) // Hopefully this executes in the correct order.
 }
 
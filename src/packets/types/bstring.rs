@@ -2,6 +2,7 @@ use crate::packets::serial;
 
 use super::v32;
 
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct BoundedString<const L: usize> {
     pub(super) value: String,
 }
@@ -21,15 +22,25 @@ impl<const L: usize> serial::Encode for BoundedString<L> {
 
 impl<const L: usize> serial::Decode for BoundedString<L> {
     fn decode(decoder: &mut serial::Decoder) -> Result<Self, serial::DecodeError> {
-        let len = u32::from(<v32 as serial::Decode>::decode(decoder)?);
+        let len = u32::from(<v32 as serial::Decode>::decode(decoder)?) as usize;
 
-        let mut bytes = Vec::<u8>::with_capacity(len as usize);
+        // Each character takes up at most 4 bytes
+        if len > L * 4 {
+            return Err(serial::DecodeError::InvalidData);
+        }
+
+        let mut bytes = Vec::<u8>::with_capacity(len.min(decoder.remaining()));
         for _ in 0..len {
             bytes.push(serial::Decode::decode(decoder)?);
         }
 
-        let value = String::from_utf8(bytes).map_err(|_| serial::DecodeError::InvalidData)?; // Should be fine?
-        Ok(BoundedString::<L>::from(value))
+        let value = String::from_utf8(bytes).map_err(|_| serial::DecodeError::InvalidData)?;
+
+        // Unlike From<String>, bad input from the client shouldn't panic
+        if value.chars().count() > L {
+            return Err(serial::DecodeError::InvalidData);
+        }
+        Ok(Self { value })
     }
 }
 
@@ -70,3 +81,9 @@ impl<const L: usize> std::fmt::Display for BoundedString<L> {
         write!(f, "{}", self.value)
     }
 }
+
+impl<const L: usize> std::fmt::Debug for BoundedString<L> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.value)
+    }
+}
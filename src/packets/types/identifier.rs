@@ -1,33 +1,55 @@
 use super::BoundedString;
 
+use crate::packets::serial;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct Identifier(BoundedString<32767>);
 
-impl From<String> for Identifier {
-    fn from(value: String) -> Self {
-        // Add default namespace if none is specified
+impl Identifier {
+    fn is_valid(value: &str) -> bool {
         let mut parts = value.split(':');
-        let mut namespace = parts.next().unwrap().to_string();
-        let path = parts.next().map(|e| e.to_string()).unwrap_or_else(|| {
-            let path = namespace.clone();
-            namespace = "minecraft".to_string();
-            path
-        });
-
-        // Check if Identifier is valid
-        let value = format!("{}:{}", namespace, path);
-        for part in value.split(':') {
-            assert!(
-                part.chars().all(|c| c.is_ascii_lowercase()
+        let valid = parts.by_ref().take(2).all(|part| {
+            part.chars().all(|c| {
+                c.is_ascii_lowercase()
                     || c.is_ascii_digit()
                     || c == '_'
                     || c == '.'
                     || c == '/'
-                    || c == '-'),
-                "Invalid Identifier"
-            );
-        }
+                    || c == '-'
+            })
+        });
+        valid && parts.next().is_none()
+    }
+
+    /// Like From<String>, but returns None instead of panicking on invalid input
+    pub fn parse(value: &str) -> Option<Self> {
+        // Add default namespace if none is specified
+        let value = match value.contains(':') {
+            true => value.to_string(),
+            false => format!("minecraft:{}", value),
+        };
+        (value.len() <= 32767 && Self::is_valid(&value))
+            .then(|| Self(BoundedString::<32767>::from(value)))
+    }
+}
+
+impl serial::Encode for Identifier {
+    fn encode(&self, encoder: &mut serial::Encoder) -> Result<(), serial::EncodeError> {
+        serial::Encode::encode(&self.0, encoder)
+    }
+}
+
+impl serial::Decode for Identifier {
+    fn decode(decoder: &mut serial::Decoder) -> Result<Self, serial::DecodeError> {
+        let value = <BoundedString<32767> as serial::Decode>::decode(decoder)?;
+        // Namespaces are optional on the wire too, "brand" is the same channel as "minecraft:brand"
+        Self::parse(&value.value).ok_or(serial::DecodeError::InvalidData)
+    }
+}
 
-        Self(BoundedString::<32767>::from(value))
+impl From<String> for Identifier {
+    fn from(value: String) -> Self {
+        Self::parse(&value).expect("Invalid Identifier")
     }
 }
 
@@ -42,3 +64,63 @@ impl From<Identifier> for String {
         value.0.value
     }
 }
+
+impl std::fmt::Display for Identifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::fmt::Debug for Identifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(value: &str) -> Result<Identifier, serial::DecodeError> {
+        let bytes = serial::encode_to_vec(&BoundedString::<32767>::from(value)).unwrap();
+        serial::decode_from_slice::<Identifier>(&bytes).map(|(identifier, _)| identifier)
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            Identifier::parse("minecraft:brand").unwrap().to_string(),
+            "minecraft:brand"
+        );
+        assert_eq!(
+            Identifier::parse("velocity:player_info")
+                .unwrap()
+                .to_string(),
+            "velocity:player_info"
+        );
+        assert_eq!(
+            Identifier::parse("brand").unwrap().to_string(),
+            "minecraft:brand"
+        );
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(Identifier::parse("Minecraft:brand").is_none());
+        assert!(Identifier::parse("minecraft:brand:extra").is_none());
+        assert!(Identifier::parse("minecraft:br and").is_none());
+    }
+
+    #[test]
+    fn decode_default_namespace() {
+        assert_eq!(
+            decode("brand").ok().unwrap(),
+            Identifier::from("minecraft:brand")
+        );
+        assert_eq!(
+            decode("bungeecord:main").ok().unwrap(),
+            Identifier::from("bungeecord:main")
+        );
+        assert!(decode("BRAND").is_err());
+    }
+}
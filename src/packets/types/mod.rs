@@ -1,11 +1,15 @@
 mod bstring;
 mod chat;
 mod identifier;
+mod property;
+mod uuid;
 mod varint;
 
 pub use bstring::BoundedString;
 pub use chat::Chat;
 pub use identifier::Identifier;
+pub use property::Property;
+pub use uuid::Uuid;
 pub use varint::v32;
 
 pub use super::PacketState as ConnectionState;
@@ -0,0 +1,44 @@
+use serde::Deserialize;
+
+use super::BoundedString;
+
+use crate::packets::serial;
+
+/// A game profile property, e.g. the player's skin under "textures".
+/// Comes from the session server, or a proxy forwarding the profile.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Property {
+    pub name: String,
+    pub value: String,
+    pub signature: Option<String>,
+}
+
+/// Serialization
+impl serial::Encode for Property {
+    fn encode(&self, encoder: &mut serial::Encoder) -> Result<(), serial::EncodeError> {
+        serial::Encode::encode(&BoundedString::<32767>::from(self.name.as_str()), encoder)?;
+        serial::Encode::encode(&BoundedString::<32767>::from(self.value.as_str()), encoder)?;
+        serial::Encode::encode(&self.signature.is_some(), encoder)?;
+        if let Some(signature) = &self.signature {
+            serial::Encode::encode(&BoundedString::<32767>::from(signature.as_str()), encoder)?;
+        }
+        Ok(())
+    }
+}
+
+impl serial::Decode for Property {
+    fn decode(decoder: &mut serial::Decoder) -> Result<Self, serial::DecodeError> {
+        let name = <BoundedString<32767> as serial::Decode>::decode(decoder)?.into();
+        let value = <BoundedString<32767> as serial::Decode>::decode(decoder)?.into();
+        let signature = if <bool as serial::Decode>::decode(decoder)? {
+            Some(<BoundedString<32767> as serial::Decode>::decode(decoder)?.into())
+        } else {
+            None
+        };
+        Ok(Self {
+            name,
+            value,
+            signature,
+        })
+    }
+}
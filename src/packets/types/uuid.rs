@@ -0,0 +1,139 @@
+use md5::{Digest, Md5};
+
+use crate::packets::serial;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Uuid {
+    value: u128,
+}
+
+impl Uuid {
+    /// The UUID vanilla assigns to players in offline mode, a v3 UUID of `OfflinePlayer:<name>`
+    pub fn offline(username: &str) -> Self {
+        let mut hash: [u8; 16] = Md5::new()
+            .chain_update(b"OfflinePlayer:")
+            .chain_update(username.as_bytes())
+            .finalize()
+            .into();
+
+        hash[6] = (hash[6] & 0x0F) | 0x30; // Version 3
+        hash[8] = (hash[8] & 0x3F) | 0x80; // IETF variant
+
+        Self {
+            value: u128::from_be_bytes(hash),
+        }
+    }
+
+    /// Parses both the hyphenated and the plain 32 digit form
+    pub fn parse(value: &str) -> Option<Self> {
+        let hex = if value.len() == 36 {
+            let bytes = value.as_bytes();
+            if [8, 13, 18, 23].iter().any(|&i| bytes[i] != b'-') {
+                return None;
+            }
+            value.replace('-', "")
+        } else {
+            value.to_string()
+        };
+
+        if hex.len() != 32 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+
+        u128::from_str_radix(&hex, 16)
+            .ok()
+            .map(|value| Self { value })
+    }
+}
+
+/// Serialization
+impl serial::Encode for Uuid {
+    fn encode(&self, encoder: &mut serial::Encoder) -> Result<(), serial::EncodeError> {
+        serial::Encode::encode(&((self.value >> 64) as u64), encoder)?;
+        serial::Encode::encode(&(self.value as u64), encoder)?;
+        Ok(())
+    }
+}
+
+impl serial::Decode for Uuid {
+    fn decode(decoder: &mut serial::Decoder) -> Result<Self, serial::DecodeError> {
+        let most = <u64 as serial::Decode>::decode(decoder)? as u128;
+        let least = <u64 as serial::Decode>::decode(decoder)? as u128;
+        Ok(Self {
+            value: (most << 64) | least,
+        })
+    }
+}
+
+/// Integer convertions
+impl From<u128> for Uuid {
+    fn from(value: u128) -> Self {
+        Self { value }
+    }
+}
+
+impl From<Uuid> for u128 {
+    fn from(value: Uuid) -> Self {
+        value.value
+    }
+}
+
+/// Output
+impl std::fmt::Display for Uuid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let hex = format!("{:032x}", self.value);
+        write!(
+            f,
+            "{}-{}-{}-{}-{}",
+            &hex[0..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..32]
+        )
+    }
+}
+
+impl std::fmt::Debug for Uuid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offline() {
+        // What a vanilla server in offline mode gives Notch
+        assert_eq!(
+            Uuid::offline("Notch").to_string(),
+            "b50ad385-829d-3141-a216-7e7d7539ba7f"
+        );
+    }
+
+    #[test]
+    fn parse_hyphenated() {
+        let text = "069a79f4-44e9-4726-a5be-fca90e38aaf5";
+        let uuid = Uuid::parse(text).unwrap();
+        assert_eq!(u128::from(uuid), 0x069a79f444e94726a5befca90e38aaf5);
+        assert_eq!(uuid.to_string(), text);
+    }
+
+    #[test]
+    fn parse_plain() {
+        // The session server leaves out the hyphens
+        let uuid = Uuid::parse("069a79f444e94726a5befca90e38aaf5").unwrap();
+        assert_eq!(uuid.to_string(), "069a79f4-44e9-4726-a5be-fca90e38aaf5");
+        assert_eq!(Uuid::parse(&uuid.to_string()), Some(uuid));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert_eq!(Uuid::parse(""), None);
+        assert_eq!(Uuid::parse("069a79f4-44e9-4726-a5be-fca90e38aaf"), None);
+        assert_eq!(Uuid::parse("069a79f4444e9-4726-a5be-fca90e38aaf5"), None);
+        assert_eq!(Uuid::parse("069a79f444e94726a5befca90e38aafg"), None);
+    }
+}
@@ -1,12 +1,16 @@
 use crate::packets::serial;
 
 #[allow(non_camel_case_types)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub struct v32 {
     value: u32,
 }
 
 impl v32 {
+    pub const fn new(value: u32) -> Self {
+        Self { value }
+    }
+
     pub const fn byte_size(val: u32) -> usize {
         match val {
             0..=0x7F => 1,
@@ -78,3 +82,16 @@ impl From<v32> for i32 {
         value.value as i32
     }
 }
+
+/// Output
+impl std::fmt::Display for v32 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.value as i32)
+    }
+}
+
+impl std::fmt::Debug for v32 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
@@ -0,0 +1,47 @@
+pub struct ProtocolVersion {
+    pub protocol: u32,
+    pub name: &'static str,
+}
+
+// Oldest first. Packets with ids that differ between these are declared with version ranges in `packets!`
+pub const SUPPORTED_VERSIONS: &[ProtocolVersion] = &[
+    ProtocolVersion {
+        protocol: 754,
+        name: "1.16.5",
+    },
+    ProtocolVersion {
+        protocol: 755,
+        name: "1.17",
+    },
+    ProtocolVersion {
+        protocol: 756,
+        name: "1.17.1",
+    },
+    ProtocolVersion {
+        protocol: 757,
+        name: "1.18.1",
+    },
+    ProtocolVersion {
+        protocol: 758,
+        name: "1.18.2",
+    },
+];
+
+impl ProtocolVersion {
+    pub fn lookup(protocol: u32) -> Option<&'static Self> {
+        SUPPORTED_VERSIONS.iter().find(|v| v.protocol == protocol)
+    }
+
+    pub fn latest() -> &'static Self {
+        SUPPORTED_VERSIONS.last().unwrap()
+    }
+
+    /// e.g. "1.16.5-1.18.2"
+    pub fn supported_range() -> String {
+        format!(
+            "{}-{}",
+            SUPPORTED_VERSIONS.first().unwrap().name,
+            Self::latest().name
+        )
+    }
+}
@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+
+use slotmap::DefaultKey;
+
+use crate::packets::types::Identifier;
+
+use super::Server;
+
+// Channels every client and server knows about
+pub const REGISTER: &str = "minecraft:register";
+pub const UNREGISTER: &str = "minecraft:unregister";
+pub const BRAND: &str = "minecraft:brand";
+
+/// Called with the key of the player that sent the message, and the message's data
+pub type ChannelHandler = Box<dyn FnMut(&mut Server, DefaultKey, &[u8]) + Send>;
+
+/// Plugin message handlers, keyed by the channel they listen on
+#[derive(Default)]
+pub struct Channels {
+    handlers: HashMap<Identifier, ChannelHandler>,
+}
+
+impl Channels {
+    /// Replaces any handler already registered on `channel`
+    pub fn register(&mut self, channel: Identifier, handler: ChannelHandler) {
+        self.handlers.insert(channel, handler);
+    }
+
+    // Handlers get `&mut Server`, so they're taken out of the registry while they run
+    pub(super) fn take(&mut self, channel: &Identifier) -> Option<ChannelHandler> {
+        self.handlers.remove(channel)
+    }
+
+    pub(super) fn restore(&mut self, channel: Identifier, handler: ChannelHandler) {
+        // Don't clobber a handler that was registered while this one ran
+        self.handlers.entry(channel).or_insert(handler);
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &Identifier> {
+        self.handlers.keys()
+    }
+}
+
+/// Splits a `minecraft:register`/`unregister` payload into channels. Invalid names are skipped
+pub fn parse_channel_list(data: &[u8]) -> Vec<Identifier> {
+    data.split(|byte| *byte == 0)
+        .filter_map(|name| std::str::from_utf8(name).ok())
+        .filter(|name| !name.is_empty())
+        .filter_map(Identifier::parse)
+        .collect()
+}
+
+pub fn encode_channel_list<'a>(channels: impl Iterator<Item = &'a Identifier>) -> Vec<u8> {
+    channels
+        .map(|channel| channel.to_string())
+        .collect::<Vec<_>>()
+        .join("\0")
+        .into_bytes()
+}
@@ -1,32 +1,212 @@
+mod channels;
 mod player;
 
+use std::collections::HashSet;
 use std::sync::{atomic::AtomicBool, Arc};
+use std::time::{Duration, Instant};
 
-use log::{debug, trace};
+use channels::Channels;
+use log::{debug, info, trace, warn};
 use player::Player;
 use slotmap::{DefaultKey, DenseSlotMap};
 
 use crate::{
-    network::{connection::ServerConnection, NetworkManager},
-    packets::Packets,
+    config::{FlushPolicy, CONFIG},
+    network::{
+        capture::Direction,
+        connection::ServerConnection,
+        metrics::{NetworkMetrics, TrafficMetrics},
+        status, NetworkManager,
+    },
+    packets::{
+        self, serial,
+        types::{BoundedString, Identifier},
+        Packets,
+    },
 };
 
+// Sent to clients on `minecraft:brand`, shown in the F3 screen
+const BRAND: &str = "SnapRS";
+// Packet types listed in the traffic summary logged on shutdown
+const TOP_PACKETS: usize = 10;
+// Same limit as Bukkit, stops a client from registering channels until we run out of memory
+const MAX_CHANNELS: usize = 128;
+// Vanilla's tick length. Connections are flushed this often with the "end_of_tick" flush policy
+const TICK: Duration = Duration::from_millis(50);
+
 pub struct Server {
     network_manager: NetworkManager,
     players: DenseSlotMap<DefaultKey, Player>,
 
+    channels: Channels,
+    // Plugin messages queued by handlers, sent once every connection's packets have been processed
+    outbox: Vec<(DefaultKey, Packets)>,
+    // The server doesn't tick yet, so the end of a tick is tracked separately from `process_connections`
+    last_flush: Instant,
+
     pub running: Arc<AtomicBool>,
 }
 
 impl Server {
     pub fn new(running: Arc<AtomicBool>) -> Self {
-        Self {
+        let mut server = Self {
             network_manager: NetworkManager::new(),
             players: DenseSlotMap::new(),
+            channels: Channels::default(),
+            outbox: Vec::new(),
+            last_flush: Instant::now(),
             running,
+        };
+
+        server.register_channel(
+            Identifier::from(channels::REGISTER),
+            |server, player, data| {
+                if let Some(player) = server.players.get_mut(player) {
+                    for channel in channels::parse_channel_list(data) {
+                        if player.channels.len() >= MAX_CHANNELS {
+                            warn!("{} registered too many channels", player.username);
+                            break;
+                        }
+                        player.channels.insert(channel);
+                    }
+                }
+            },
+        );
+        server.register_channel(
+            Identifier::from(channels::UNREGISTER),
+            |server, player, data| {
+                if let Some(player) = server.players.get_mut(player) {
+                    for channel in channels::parse_channel_list(data) {
+                        player.channels.remove(&channel);
+                    }
+                }
+            },
+        );
+        server.register_channel(Identifier::from(channels::BRAND), |server, player, data| {
+            if let Some(player) = server.players.get_mut(player) {
+                match serial::decode_from_slice::<BoundedString<32767>>(data) {
+                    Ok((brand, _)) => {
+                        debug!("{} is using client brand '{}'", player.username, brand);
+                        player.brand = Some(brand.to_string());
+                    }
+                    Err(_) => debug!("{} sent an invalid client brand", player.username),
+                }
+            }
+        });
+
+        server
+    }
+
+    /// Calls `handler` with plugin messages players send on `channel`, replacing any existing handler
+    pub fn register_channel(
+        &mut self,
+        channel: Identifier,
+        handler: impl FnMut(&mut Server, DefaultKey, &[u8]) + Send + 'static,
+    ) {
+        self.channels.register(channel, Box::new(handler));
+    }
+
+    /// Queues a plugin message to `player`, sent at the end of the tick.
+    /// Channels outside the `minecraft` namespace are only sent once the client has registered them.
+    pub fn send_plugin_message(&mut self, player: DefaultKey, channel: Identifier, data: Vec<u8>) {
+        let player = match self.players.get(player) {
+            Some(player) => player,
+            None => return,
+        };
+        if !channel.to_string().starts_with("minecraft:") && !player.channels.contains(&channel) {
+            debug!(
+                "Not sending {} to {}, it isn't registered",
+                channel, player.username
+            );
+            return;
+        }
+
+        self.outbox.push((
+            player.key,
+            Packets::from(packets::clientbound::play_packets::PluginMessage { channel, data }),
+        ));
+    }
+
+    /// Starts or stops recording `player`'s packets to the capture file, regardless of the config's player filter
+    pub fn set_capture(&mut self, player: DefaultKey, enabled: bool) {
+        if let Some(player) = self.players.get(player) {
+            player.capture.set_enabled(enabled);
         }
     }
 
+    fn handle_plugin_message(&mut self, player: DefaultKey, channel: Identifier, data: &[u8]) {
+        match self.channels.take(&channel) {
+            Some(mut handler) => {
+                handler(self, player, data);
+                self.channels.restore(channel, handler);
+            }
+            None => debug!("Unhandled plugin message on {}", channel),
+        }
+    }
+
+    /// Traffic of every connection since the server started, including ones that have closed
+    pub fn network_metrics(&self) -> NetworkMetrics {
+        NetworkMetrics::collect(self.players.values().map(|player| player.metrics.as_ref()))
+    }
+
+    /// Traffic of `player`'s connection, including what was sent before they logged in
+    pub fn player_traffic(&self, player: DefaultKey) -> Option<TrafficMetrics> {
+        self.players
+            .get(player)
+            .map(|player| player.metrics.snapshot())
+    }
+
+    fn log_network_metrics(&self) {
+        let metrics = self.network_metrics();
+        for direction in [Direction::Serverbound, Direction::Clientbound] {
+            let total = metrics.traffic.total(direction);
+            debug!(
+                "{}: {} packets, {} bytes ({} on the wire, ratio {:.2}), {:?} spent",
+                direction,
+                total.count,
+                total.raw_bytes,
+                total.wire_bytes,
+                total.compression_ratio(),
+                total.time
+            );
+        }
+        debug!(
+            "Rate limited {} clients for packets, {} for bytes",
+            metrics.packet_limit_hits, metrics.byte_limit_hits
+        );
+
+        let mut packets = metrics.traffic.iter().collect::<Vec<_>>();
+        packets.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.wire_bytes));
+        for (key, stats) in packets.into_iter().take(TOP_PACKETS) {
+            debug!(
+                "{}: {} packets, {} bytes on the wire, ratio {:.2}, {:?} spent",
+                key,
+                stats.count,
+                stats.wire_bytes,
+                stats.compression_ratio(),
+                stats.time
+            );
+        }
+    }
+
+    // Called whenever a player joins or leaves
+    async fn update_online(&self) {
+        let players = self
+            .players
+            .values()
+            .map(|player| status::Player::new(&player.username, player.uuid))
+            .collect();
+        self.network_manager.online.set_players(players).await;
+    }
+
+    // Players are keyed separately from their connections
+    fn player_key(&self, connection: DefaultKey) -> Option<DefaultKey> {
+        self.players
+            .iter()
+            .find(|(_, player)| player.key == connection)
+            .map(|(key, _)| key)
+    }
+
     pub async fn start(&mut self) {
         self.network_manager.start().await;
 
@@ -36,6 +216,7 @@ impl Server {
             self.process_connections().await;
         }
         debug!("Server stopped");
+        self.log_network_metrics();
 
         self.network_manager.stop().await;
     }
@@ -65,12 +246,52 @@ impl Server {
             }
         }
 
+        for (key, packet) in self.outbox.drain(..) {
+            if let Some(connection) = connections.get(key) {
+                // Waiting on one slow client would hold up the whole tick
+                if connection.lock().await.outgoing.try_send(packet).is_err() {
+                    warn!(
+                        "Dropped plugin message to {:?}, its connection is backed up",
+                        key
+                    );
+                }
+            }
+        }
+
+        if CONFIG.network.advanced.flush_policy == FlushPolicy::EndOfTick
+            && self.last_flush.elapsed() >= TICK
+        {
+            self.last_flush = Instant::now();
+            for connection in connections.values() {
+                // A full channel means the writer is still busy, it'll get the next one
+                let _ = connection
+                    .lock()
+                    .await
+                    .outgoing
+                    .try_send(Packets::from(packets::internal::network_packets::Flush {}));
+            }
+        }
+
         if !disconnections.is_empty() {
             drop(connections);
             let mut connections = self.network_manager.connections.write().await;
             for key in disconnections {
                 connections.remove(key);
+                if let Some(player) = self.player_key(key) {
+                    if let Some(traffic) = self.player_traffic(player) {
+                        debug!(
+                            "{} sent {} bytes and received {} bytes",
+                            self.players[player].username,
+                            traffic.total(Direction::Serverbound).wire_bytes,
+                            traffic.total(Direction::Clientbound).wire_bytes
+                        );
+                    }
+                    let player = self.players.remove(player).unwrap();
+                    self.network_manager.online.free_slot();
+                    info!("{} ({}) left", player.username, player.address);
+                }
             }
+            self.update_online().await;
         }
     }
 
@@ -82,11 +303,52 @@ impl Server {
     ) {
         match packet {
             Packets::InternalServerInitalize(packet) => {
-                self.players.insert(Player {
+                info!("{} joined from {}", packet.username, packet.address);
+                let player = self.players.insert(Player {
                     key,
                     username: packet.username,
                     uuid: packet.uuid,
+                    address: packet.address,
+                    latency: 0,
+                    channels: HashSet::new(),
+                    brand: None,
+                    capture: connection.capture.clone(),
+                    metrics: connection.metrics.clone(),
                 });
+
+                let capture = &CONFIG.capture;
+                if capture.enabled
+                    && capture
+                        .players
+                        .iter()
+                        .any(|name| name.eq_ignore_ascii_case(&self.players[player].username))
+                {
+                    self.set_capture(player, true);
+                }
+                self.update_online().await;
+
+                // Tell the client who we are, and which of its mod's channels we listen on
+                let brand = serial::encode_to_vec(&BoundedString::<32767>::from(BRAND)).unwrap();
+                self.send_plugin_message(player, Identifier::from(channels::BRAND), brand);
+                let custom = self
+                    .channels
+                    .names()
+                    .filter(|channel| !channel.to_string().starts_with("minecraft:"))
+                    .collect::<Vec<_>>();
+                if !custom.is_empty() {
+                    let data = channels::encode_channel_list(custom.into_iter());
+                    self.send_plugin_message(player, Identifier::from(channels::REGISTER), data);
+                }
+            }
+            Packets::InternalServerLatency(packet) => {
+                if let Some(player) = self.player_key(key) {
+                    self.players[player].latency = packet.latency;
+                }
+            }
+            Packets::ServerboundPlayPluginMessage(packet) => {
+                if let Some(player) = self.player_key(key) {
+                    self.handle_plugin_message(player, packet.channel, &packet.data);
+                }
             }
             _ => {}
         }
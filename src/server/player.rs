@@ -1,7 +1,27 @@
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
 use slotmap::DefaultKey;
 
+use crate::{
+    network::{capture::Capture, metrics::ConnectionMetrics},
+    packets::types::{Identifier, Uuid},
+};
+
 pub(super) struct Player {
     pub key: DefaultKey,
     pub username: String,
-    pub uuid: String,
+    pub uuid: Uuid,
+    pub address: SocketAddr,
+    // Keep-alive round trip in milliseconds, shown in the tab list
+    pub latency: u32,
+
+    // Plugin channels the client has registered through `minecraft:register`
+    pub channels: HashSet<Identifier>,
+    // What the client reported on `minecraft:brand`, e.g. "vanilla" or "fabric"
+    pub brand: Option<String>,
+
+    pub capture: Arc<Capture>,
+    pub metrics: Arc<ConnectionMetrics>,
 }